@@ -1,18 +1,185 @@
 use super::lines;
+use std::fmt;
+use std::fs;
 
 pub fn run() {
-    let distance = manhatten_distance("day-12-input.txt");
-    println!("manhatten distance: {}", distance);
+    match report("day-12-input.txt") {
+        Ok(reports) => reports.into_iter().for_each(|r| println!("{}", r)),
+        Err(e) => println!("could not navigate: {:?}", e)
+    }
+    vec!(NavigationMode::Heading, NavigationMode::Waypoint).into_iter().for_each(|mode| {
+        match export_trace("day-12-input.txt", mode, "day-12-trace") {
+            Ok(files) => println!("wrote {:?} trace to {}", mode, files.join(", ")),
+            Err(e) => println!("could not export {:?} trace: {}", mode, e)
+        }
+    });
 }
 
 fn read_instructions(file: &str) -> Vec<Instruction> {
     lines(file).unwrap().into_iter().flat_map(Instruction::new).collect()
 }
 
-fn manhatten_distance(file: &str) -> isize {
+fn navigate(instructions: Vec<Instruction>, mode: NavigationMode) -> Result<Ship, NavigationError> {
+    instructions.into_iter().try_fold(Ship::new(), |ship, instruction| ship.follow(mode, instruction))
+}
+
+fn manhatten_distance(file: &str, mode: NavigationMode) -> Result<isize, NavigationError> {
+    navigate(read_instructions(file), mode).map(|ship| ship.position.distance())
+}
+
+fn trace(instructions: Vec<Instruction>, mode: NavigationMode) -> Result<Trace, NavigationError> {
+    let start = Ship::new();
+    instructions.into_iter()
+        .try_fold((start, vec!(Step::of(None, start))), |(ship, mut steps), instruction| {
+            ship.follow(mode, instruction).map(|next| {
+                steps.push(Step::of(Some(instruction), next));
+                (next, steps)
+            })
+        })
+        .map(|(_, steps)| Trace { mode, steps })
+}
+
+fn export_trace(file: &str, mode: NavigationMode, prefix: &str) -> Result<Vec<String>, String> {
+    let trace = trace(read_instructions(file), mode).map_err(|e| format!("{:?}", e))?;
+    let name = format!("{}-{}", prefix, format!("{:?}", mode).to_lowercase());
+    let svg = format!("{}.svg", name);
+    let csv = format!("{}.csv", name);
+    fs::write(&svg, trace.to_svg()).map_err(|e| e.to_string())?;
+    fs::write(&csv, trace.to_csv()).map_err(|e| e.to_string())?;
+    Ok(vec!(svg, csv))
+}
+
+fn report(file: &str) -> Result<Vec<Report>, NavigationError> {
     let instructions = read_instructions(file);
-    let ship = instructions.into_iter().fold(Ship::new(), |ship, instruction| ship.follow(instruction));
-    ship.position.x.abs() + ship.position.y.abs()
+    vec!(NavigationMode::Heading, NavigationMode::Waypoint).into_iter()
+        .map(|mode| navigate(instructions.clone(), mode).map(|ship| Report { mode, ship }))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum NavigationMode {
+    Heading,
+    Waypoint
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum NavigationError {
+    IllegalTurn(isize)
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Report {
+    mode: NavigationMode,
+    ship: Ship
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let position = self.ship.position;
+        match self.mode {
+            NavigationMode::Heading => write!(f, "heading: position ({}, {}), facing {:?}, manhatten distance {}",
+                                              position.x, position.y, self.ship.facing, position.distance()),
+            NavigationMode::Waypoint => write!(f, "waypoint: position ({}, {}), waypoint ({}, {}), manhatten distance {}",
+                                               position.x, position.y, self.ship.waypoint.x, self.ship.waypoint.y, position.distance())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Step {
+    instruction: Option<Instruction>,
+    ship: Coordinate,
+    waypoint: Coordinate
+}
+
+impl Step {
+    fn of(instruction: Option<Instruction>, ship: Ship) -> Step {
+        Step {
+            instruction,
+            ship: ship.position,
+            waypoint: ship.position.shift_x(ship.waypoint.x).shift_y(ship.waypoint.y)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Trace {
+    mode: NavigationMode,
+    steps: Vec<Step>
+}
+
+const SVG_SIZE: f64 = 800.0;
+const SVG_PADDING: f64 = 20.0;
+
+impl Trace {
+    fn to_csv(&self) -> String {
+        let mut csv = vec!("step,instruction,ship_x,ship_y,waypoint_x,waypoint_y".to_string());
+        csv.extend(self.steps.iter().enumerate().map(|(idx, step)|
+            format!("{},{},{},{},{},{}",
+                    idx,
+                    step.instruction.map(|i| i.to_string()).unwrap_or_default(),
+                    step.ship.x, step.ship.y,
+                    step.waypoint.x, step.waypoint.y)));
+        csv.join("\n")
+    }
+
+    fn points(&self) -> Vec<Coordinate> {
+        let waypoints = self.steps.iter()
+            .filter(|_| self.mode == NavigationMode::Waypoint)
+            .map(|s| s.waypoint);
+        self.steps.iter().map(|s| s.ship).chain(waypoints).collect()
+    }
+
+    fn bounds(&self) -> (Coordinate, Coordinate) {
+        let points = self.points();
+        let min = Coordinate::of(
+            points.iter().map(|c| c.x).min().unwrap_or(0),
+            points.iter().map(|c| c.y).min().unwrap_or(0));
+        let max = Coordinate::of(
+            points.iter().map(|c| c.x).max().unwrap_or(0),
+            points.iter().map(|c| c.y).max().unwrap_or(0));
+        (min, max)
+    }
+
+    fn to_svg(&self) -> String {
+        let (min, max) = self.bounds();
+        let span = (max.x - min.x).max(max.y - min.y).max(1) as f64;
+        let scale = (SVG_SIZE - 2.0 * SVG_PADDING) / span;
+        let width = (max.x - min.x) as f64 * scale + 2.0 * SVG_PADDING;
+        let height = (max.y - min.y) as f64 * scale + 2.0 * SVG_PADDING;
+        // svg y grows downwards, so north is flipped to the top of the image
+        let project = |c: Coordinate| (
+            (c.x - min.x) as f64 * scale + SVG_PADDING,
+            (max.y - c.y) as f64 * scale + SVG_PADDING
+        );
+        let polyline = |points: Vec<Coordinate>, color: &str| format!(
+            "  <polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1\" points=\"{}\"/>",
+            color,
+            points.into_iter()
+                .map(|c| { let (x, y) = project(c); format!("{:.2},{:.2}", x, y) })
+                .collect::<Vec<String>>()
+                .join(" "));
+        let marker = |c: Coordinate, color: &str| {
+            let (x, y) = project(c);
+            format!("  <circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"4\" fill=\"{}\"/>", x, y, color)
+        };
+
+        let mut svg = vec!(
+            format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" viewBox=\"0 0 {:.2} {:.2}\">",
+                    width, height, width, height),
+            format!("  <rect width=\"{:.2}\" height=\"{:.2}\" fill=\"white\"/>", width, height)
+        );
+        if self.mode == NavigationMode::Waypoint {
+            svg.push(polyline(self.steps.iter().map(|s| s.waypoint).collect(), "orange"));
+        }
+        svg.push(polyline(self.steps.iter().map(|s| s.ship).collect(), "blue"));
+        if let (Some(start), Some(end)) = (self.steps.first(), self.steps.last()) {
+            svg.push(marker(start.ship, "green"));
+            svg.push(marker(end.ship, "red"));
+        }
+        svg.push("</svg>".to_string());
+        svg.join("\n")
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -41,12 +208,24 @@ use Side::*;
 use Action::*;
 
 impl Side {
-    fn turn_degrees(&self, waypoint: Coordinate, degrees: isize) -> Coordinate {
-        let num_turns = degrees / 90;
-        (0..num_turns).into_iter().fold(waypoint, |coordinate, _| {
-            self.turn(coordinate)
-        })
+    fn quarter_turns(degrees: isize) -> Result<isize, NavigationError> {
+        if degrees % 90 == 0 {
+            Ok((degrees / 90).rem_euclid(4))
+        } else {
+            Err(NavigationError::IllegalTurn(degrees))
+        }
     }
+
+    fn turn_degrees(&self, waypoint: Coordinate, degrees: isize) -> Result<Coordinate, NavigationError> {
+        Side::quarter_turns(degrees).map(|num_turns|
+            (0..num_turns).fold(waypoint, |coordinate, _| self.turn(coordinate)))
+    }
+
+    fn turn_direction_degrees(&self, facing: Direction, degrees: isize) -> Result<Direction, NavigationError> {
+        Side::quarter_turns(degrees).map(|num_turns|
+            (0..num_turns).fold(facing, |direction, _| direction.turn(*self)))
+    }
+
     fn turn(&self, waypoint: Coordinate) -> Coordinate {
         match self {
             Right => Coordinate { x: waypoint.y, y: -waypoint.x },
@@ -63,6 +242,15 @@ impl Direction {
             West => coordinate.shift_x(-distance),
         }
     }
+
+    fn turn(&self, side: Side) -> Direction {
+        match (self, side) {
+            (North, Right) | (South, Left) => East,
+            (East, Right) | (West, Left) => South,
+            (South, Right) | (North, Left) => West,
+            (West, Right) | (East, Left) => North
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -89,6 +277,21 @@ impl Instruction {
     }
 }
 
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            Forward => 'F',
+            Move(North) => 'N',
+            Move(South) => 'S',
+            Move(East) => 'E',
+            Move(West) => 'W',
+            Turn(Left) => 'L',
+            Turn(Right) => 'R'
+        };
+        write!(f, "{}{}", action, self.value)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 struct Coordinate {
     x: isize,
@@ -106,9 +309,14 @@ impl Coordinate {
     fn shift_y(&self, y: isize) -> Coordinate {
         Coordinate { y: self.y + y, x: self.x }
     }
+
+    fn distance(&self) -> isize {
+        self.x.abs() + self.y.abs()
+    }
 }
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 struct Ship {
+    facing: Direction,
     position: Coordinate,
     waypoint: Coordinate
 }
@@ -116,19 +324,33 @@ struct Ship {
 impl Ship {
     fn new() -> Ship {
         Ship {
+            facing: East,
             position: Coordinate::of(0, 0),
             waypoint: Coordinate::of(10, 1)
         }
     }
-    fn follow(&self, instruction: Instruction) -> Ship {
+    fn follow(&self, mode: NavigationMode, instruction: Instruction) -> Result<Ship, NavigationError> {
+        match mode {
+            NavigationMode::Heading => self.follow_heading(instruction),
+            NavigationMode::Waypoint => self.follow_waypoint(instruction)
+        }
+    }
+
+    fn follow_heading(&self, instruction: Instruction) -> Result<Ship, NavigationError> {
+        match instruction.action {
+            Forward => Ok(Ship { position: self.facing.shift(self.position, instruction.value), ..*self }),
+            Move(direction) => Ok(Ship { position: direction.shift(self.position, instruction.value), ..*self }),
+            Turn(side) => side.turn_direction_degrees(self.facing, instruction.value)
+                .map(|facing| Ship { facing, ..*self })
+        }
+    }
+
+    fn follow_waypoint(&self, instruction: Instruction) -> Result<Ship, NavigationError> {
         match instruction.action {
-            Forward => Ship { position: self.position.shift_x(self.waypoint.x * instruction.value).shift_y(self.waypoint.y * instruction.value), waypoint: self.waypoint },
-            Move(direction) => Ship { position: self.position, waypoint: direction.shift(self.waypoint, instruction.value) },
-            Turn(side) => Ship { position: self.position, waypoint: side.turn_degrees(self.waypoint, instruction.value) },
-            _ => self.clone()
-            // Forward => Ship { facing: self.facing, position: self.facing.forward(self.position, instruction.value) },
-            // Move(direction) => Ship { facing: self.facing, position: direction.forward(self.position, instruction.value) },
-            // Turn(side) => Ship { facing: self.facing.turn_degrees(side, instruction.value), position: self.position }
+            Forward => Ok(Ship { position: self.position.shift_x(self.waypoint.x * instruction.value).shift_y(self.waypoint.y * instruction.value), ..*self }),
+            Move(direction) => Ok(Ship { waypoint: direction.shift(self.waypoint, instruction.value), ..*self }),
+            Turn(side) => side.turn_degrees(self.waypoint, instruction.value)
+                .map(|waypoint| Ship { waypoint, ..*self })
         }
     }
 }
@@ -137,32 +359,110 @@ impl Ship {
 #[cfg(test)]
 mod tests {
     use super::manhatten_distance;
-    use super::{ Ship, Instruction, Action, Direction, Side };
+    use super::{ Ship, Instruction, NavigationMode, NavigationError };
     use crate::day12::Action::{Forward, Move, Turn};
     use crate::day12::Coordinate;
-    use crate::day12::Direction::North;
-    use crate::day12::Side::Right;
+    use crate::day12::Direction::{North, East, South, West};
+    use crate::day12::Side::{Left, Right};
 
     #[test]
     fn test() {
+        let mode = NavigationMode::Waypoint;
         let ship = Ship::new();
-        let ship = ship.follow(Instruction { action: Forward, value: 10 });
+        let ship = ship.follow(mode, Instruction { action: Forward, value: 10 }).unwrap();
         assert_eq!(ship.position, Coordinate::of(100, 10));
-        let ship = ship.follow(Instruction { action: Move(North), value: 3});
+        let ship = ship.follow(mode, Instruction { action: Move(North), value: 3}).unwrap();
         assert_eq!(ship.position, Coordinate::of(100, 10));
         assert_eq!(ship.waypoint, Coordinate::of(10, 4));
-        let ship = ship.follow(Instruction { action: Forward, value: 7});
+        let ship = ship.follow(mode, Instruction { action: Forward, value: 7}).unwrap();
         assert_eq!(ship.position, Coordinate::of(170, 38));
-        let ship = ship.follow(Instruction { action: Turn(Right), value: 90});
+        let ship = ship.follow(mode, Instruction { action: Turn(Right), value: 90}).unwrap();
         assert_eq!(ship.position, Coordinate::of(170, 38));
         assert_eq!(ship.waypoint, Coordinate::of(4, -10));
-        let ship = ship.follow(Instruction { action: Forward, value: 11});
+        let ship = ship.follow(mode, Instruction { action: Forward, value: 11}).unwrap();
         assert_eq!(ship.position, Coordinate::of(214, -72));
     }
 
+    #[test]
+    fn test_heading() {
+        let mode = NavigationMode::Heading;
+        let ship = Ship::new();
+        let ship = ship.follow(mode, Instruction { action: Forward, value: 10 }).unwrap();
+        assert_eq!(ship.position, Coordinate::of(10, 0));
+        let ship = ship.follow(mode, Instruction { action: Move(North), value: 3}).unwrap();
+        assert_eq!(ship.position, Coordinate::of(10, 3));
+        assert_eq!(ship.facing, East);
+        let ship = ship.follow(mode, Instruction { action: Turn(Right), value: 90}).unwrap();
+        assert_eq!(ship.facing, South);
+        let ship = ship.follow(mode, Instruction { action: Turn(Left), value: 450}).unwrap();
+        assert_eq!(ship.facing, East);
+        let ship = ship.follow(mode, Instruction { action: Turn(Right), value: -90}).unwrap();
+        assert_eq!(ship.facing, North);
+        let ship = ship.follow(mode, Instruction { action: Turn(Left), value: 90}).unwrap();
+        assert_eq!(ship.facing, West);
+        assert_eq!(ship.waypoint, Coordinate::of(10, 1));
+    }
+
+    #[test]
+    fn test_illegal_turn() {
+        let ship = Ship::new();
+        let turn = Instruction { action: Turn(Left), value: 45 };
+        assert_eq!(ship.follow(NavigationMode::Heading, turn), Err(NavigationError::IllegalTurn(45)));
+        assert_eq!(ship.follow(NavigationMode::Waypoint, turn), Err(NavigationError::IllegalTurn(45)));
+    }
+
     #[test]
     fn test_manhatten_distance() {
-        assert_eq!(manhatten_distance("day-12-test.txt"), 286)
+        assert_eq!(manhatten_distance("day-12-test.txt", NavigationMode::Waypoint), Ok(286));
+        assert_eq!(manhatten_distance("day-12-test.txt", NavigationMode::Heading), Ok(25));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_trace() {
+        let instructions = super::read_instructions("day-12-test.txt");
+        let trace = super::trace(instructions, NavigationMode::Waypoint).unwrap();
+        assert_eq!(trace.steps.len(), 6);
+        assert_eq!(trace.steps.iter().map(|s| s.ship).collect::<Vec<Coordinate>>(), vec!(
+            Coordinate::of(0, 0),
+            Coordinate::of(100, 10),
+            Coordinate::of(100, 10),
+            Coordinate::of(170, 38),
+            Coordinate::of(170, 38),
+            Coordinate::of(214, -72)
+        ));
+        assert_eq!(trace.steps[2].waypoint, Coordinate::of(110, 14));
+        assert_eq!(trace.bounds(), (Coordinate::of(0, -82), Coordinate::of(218, 42)));
+        assert_eq!(trace.to_csv().lines().collect::<Vec<&str>>()[..3], [
+            "step,instruction,ship_x,ship_y,waypoint_x,waypoint_y",
+            "0,,0,0,10,1",
+            "1,F10,100,10,110,11"
+        ]);
+    }
+
+    #[test]
+    fn test_trace_svg() {
+        let instructions = super::read_instructions("day-12-test.txt");
+        let svg = super::trace(instructions, NavigationMode::Heading).unwrap().to_svg();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>"));
+        assert_eq!(svg.matches("<polyline").count(), 1);
+        assert_eq!(svg.matches("<circle").count(), 2);
+
+        let instructions = super::read_instructions("day-12-test.txt");
+        let svg = super::trace(instructions, NavigationMode::Waypoint).unwrap().to_svg();
+        assert_eq!(svg.matches("<polyline").count(), 2);
+    }
+
+    #[test]
+    fn test_report() {
+        let reports = super::report("day-12-test.txt").unwrap()
+            .into_iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(reports, vec!(
+            "heading: position (17, -8), facing South, manhatten distance 25",
+            "waypoint: position (214, -72), waypoint (4, -10), manhatten distance 286"
+        ));
+    }
+
+}
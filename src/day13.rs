@@ -4,20 +4,79 @@ fn calculate_delay(bus_id: usize, after_time: usize) -> usize {
 
 use super::lines;
 fn earliest_bus(file: &str) -> (usize, usize) {
-    let lines = lines(file).unwrap();
-    let arrival_time = lines.get(0).and_then(|l| l.parse::<usize>().ok()).unwrap_or(0);
-    let mut buses_and_delays = lines.get(1)
+    let schedule = Schedule::load(file);
+    let mut buses_and_delays = schedule.next_departures(schedule.arrival_time, 1)
         .into_iter()
-        .flat_map(|l| l.split(','))
-        .flat_map(|id| id.parse::<usize>().ok())
-        .map(|bus_id| (calculate_delay(bus_id, arrival_time), bus_id))
+        .flat_map(|(bus_id, departures)| departures.first().map(|d| (d - schedule.arrival_time, bus_id)))
         .collect::<Vec<(usize, usize)>>();
     buses_and_delays.sort();
-    println!("{:?}", buses_and_delays);
-    buses_and_delays.first().unwrap_or(&(0, 0)).clone()
+    buses_and_delays.first().copied().unwrap_or((0, 0))
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Schedule {
+    arrival_time: usize,
+    buses: Vec<(usize, usize)>
+}
+
+impl Schedule {
+    fn load(file: &str) -> Schedule {
+        let lines = lines(file).unwrap();
+        Schedule::parse(
+            lines.first().map(|l| l.as_str()).unwrap_or(""),
+            lines.get(1).map(|l| l.as_str()).unwrap_or(""))
+    }
+
+    fn parse(arrival_time: &str, pattern: &str) -> Schedule {
+        Schedule {
+            arrival_time: arrival_time.trim().parse::<usize>().unwrap_or(0),
+            buses: bus_offsets(pattern.split(',').collect())
+        }
+    }
+
+    fn bus_ids(&self) -> Vec<usize> {
+        self.buses.iter().map(|(bus_id, _)| *bus_id).collect()
+    }
+
+    fn next_departures(&self, after_time: usize, count: usize) -> Vec<(usize, Vec<usize>)> {
+        self.bus_ids().into_iter()
+            .filter(|bus_id| *bus_id > 0)
+            .map(|bus_id| {
+                let first = after_time + calculate_delay(bus_id, after_time);
+                (bus_id, (0..count).map(|n| first + n * bus_id).collect())
+            })
+            .collect()
+    }
+
+    fn simultaneous_departure(&self, bus_ids: Vec<usize>, after_time: u128) -> Result<u128, ScheduleError> {
+        self.windows(bus_ids.into_iter().map(|bus_id| (bus_id, 0)).collect(), after_time, 1)
+            .map(|windows| windows.first().copied().unwrap_or(after_time))
+    }
+
+    // Each bus is given with the number of minutes it departs after the bus listed before it
+    fn windows(&self, gaps: Vec<(usize, usize)>, after_time: u128, count: usize) -> Result<Vec<u128>, ScheduleError> {
+        let known = self.bus_ids();
+        if let Some((bus_id, _)) = gaps.iter().find(|(bus_id, _)| !known.contains(bus_id)) {
+            return Err(ScheduleError::UnknownBus(*bus_id));
+        }
+        let offsets = gaps.into_iter()
+            .scan(0, |offset, (bus_id, gap)| {
+                *offset += gap;
+                Some((bus_id, *offset))
+            })
+            .collect::<Vec<(usize, usize)>>();
+        chinese_remainder(offsets).map(|(timestamp, period)| {
+            let first = if timestamp > after_time {
+                timestamp
+            } else {
+                timestamp + ((after_time - timestamp) / period + 1) * period
+            };
+            (0..count as u128).map(|n| first + n * period).collect()
+        })
+    }
 }
 
-fn find_earliest_timestamp_that_matches_file(file: &str) -> usize {
+fn find_earliest_timestamp_that_matches_file(file: &str) -> Result<u128, ScheduleError> {
 
     let lines = lines(file).unwrap();
     let pattern = lines.get(1).into_iter().flat_map(|l| l.split(',')).collect::<Vec<&str>>();
@@ -25,55 +84,79 @@ fn find_earliest_timestamp_that_matches_file(file: &str) -> usize {
 
 }
 
-fn find_earliest_timestamp_that_matches(pattern: Vec<&str>) -> usize {
-    println!("find for {:?}", pattern.clone());
-    let pattern = (0..pattern.len()).into_iter().flat_map(|idx| {
-        pattern.get(idx).and_then(|p| p.parse::<usize>().ok())
-            .map(|bus_id| (bus_id, idx + 1))
-    }).collect::<Vec<(usize, usize)>>();
-
-    let mut pattern_sort = pattern.clone();
-    pattern_sort.sort();
-    let (longest_delay, longest_pos) = pattern_sort.last().unwrap_or(&(0, 0)).clone();
-    let (second_longest_delay, second_longest_pos) = pattern_sort.get(pattern_sort.len() - 2).unwrap_or(&(0, 0)).clone();
-    let (first_delay, _) = pattern.first().unwrap_or(&(0, 0)).clone();
-    let (second_delay, second_pos) = pattern.get(1).unwrap_or(&(0, 0)).clone();
-    (0..usize::MAX).into_iter()
-        .map(|idx| {
-            //Optimization: Only check on times where the longest interval matches
-            let offset = (idx * longest_delay);
-                if(offset > longest_pos) {
-                    offset - longest_pos
-                } else {
-                    idx
-                }
-        })
-        .filter(|t| *t > 0)
-        .filter(|time| {
-            calculate_delay(second_longest_delay, *time) == second_longest_pos
-        })
-        .filter(|time| {
-            calculate_delay(first_delay, *time) == 1
-        })
-        .filter(|time| {
-            calculate_delay(second_delay, *time) == second_pos
-        })
-        .find(|time| {
-            println!("calculating at {}", time);
-            pattern.clone().into_iter().all(|(bus_id, delay)|
-                calculate_delay(bus_id, *time)  == delay
-            )
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum ScheduleError {
+    InvalidBus(usize),
+    UnknownBus(usize),
+    Inconsistent { bus_id: usize, offset: usize, modulus: u128 }
+}
+
+fn bus_offsets(pattern: Vec<&str>) -> Vec<(usize, usize)> {
+    pattern.into_iter()
+        .enumerate()
+        .flat_map(|(offset, p)| p.trim().parse::<usize>().ok().map(|bus_id| (bus_id, offset)))
+        .collect()
+}
+
+fn find_earliest_timestamp_that_matches(pattern: Vec<&str>) -> Result<u128, ScheduleError> {
+    chinese_remainder(bus_offsets(pattern)).map(|(timestamp, _)| timestamp)
+}
+
+// A bus with id b at offset o requires t + o = 0 (mod b), so every bus contributes t = -o (mod b)
+fn congruence(bus_id: usize, offset: usize) -> Result<(i128, i128), ScheduleError> {
+    if bus_id == 0 {
+        return Err(ScheduleError::InvalidBus(bus_id));
+    }
+    let modulus = bus_id as i128;
+    Ok(((-(offset as i128)).rem_euclid(modulus), modulus))
+}
+
+// Solves the system of congruences for the earliest timestamp, returned with the lcm of all bus ids
+// (the period after which the pattern repeats). Bus ids do not need to be coprime.
+fn chinese_remainder(buses: Vec<(usize, usize)>) -> Result<(u128, u128), ScheduleError> {
+    buses.into_iter()
+        .try_fold((0i128, 1i128), |(timestamp, period), (bus_id, offset)| {
+            let (residue, modulus) = congruence(bus_id, offset)?;
+            let (gcd, _, _) = modinverse::egcd(period, modulus);
+            let difference = residue - timestamp;
+            if difference % gcd != 0 {
+                return Err(ScheduleError::Inconsistent { bus_id, offset, modulus: period as u128 });
+            }
+            let reduced_modulus = modulus / gcd;
+            let inverse = modinverse::modinverse((period / gcd).rem_euclid(reduced_modulus), reduced_modulus)
+                .unwrap_or(0);
+            let steps = ((difference / gcd).rem_euclid(reduced_modulus) * inverse).rem_euclid(reduced_modulus);
+            let lcm = period * reduced_modulus;
+            Ok(((timestamp + period * steps).rem_euclid(lcm), lcm))
         })
-        .map(|t| t + 1)
-        .unwrap_or(0)
+        .map(|(timestamp, period)| (timestamp as u128, period as u128))
+}
 
+// Sieve: step through candidates by the period of the buses matched so far until the next bus also matches
+fn sieve(buses: Vec<(usize, usize)>) -> Result<(u128, u128), ScheduleError> {
+    buses.into_iter()
+        .try_fold((0u128, 1u128), |(timestamp, period), (bus_id, offset)| {
+            let (residue, modulus) = congruence(bus_id, offset)?;
+            let (residue, modulus) = (residue as u128, modulus as u128);
+            (0..modulus)
+                .map(|step| timestamp + step * period)
+                .find(|t| t % modulus == residue)
+                .map(|t| {
+                    let (gcd, _, _) = modinverse::egcd(period as i128, modulus as i128);
+                    (t, period / gcd as u128 * modulus)
+                })
+                .ok_or(ScheduleError::Inconsistent { bus_id, offset, modulus: period })
+        })
 }
+
 pub fn run() {
     let (delay, bus_id) = earliest_bus("day-13-input.txt");
     println!("The next bus is {} which will arrive in {}", bus_id, delay);
 
-    let timestamp = find_earliest_timestamp_that_matches_file("day-13-input.txt");
-    println!("Earliest timestamp matching pattern ins {}", timestamp)
+    match find_earliest_timestamp_that_matches_file("day-13-input.txt") {
+        Ok(timestamp) => println!("Earliest timestamp matching pattern is {}", timestamp),
+        Err(e) => println!("No timestamp matches the pattern: {:?}", e)
+    }
 }
 
 fn compute_first_valid_schedule(first: usize, next: usize, offset: usize) -> usize {
@@ -84,6 +167,7 @@ mod tests {
     use super::earliest_bus;
     use super::find_earliest_timestamp_that_matches;
     use crate::day13::find_earliest_timestamp_that_matches_file;
+    use super::{bus_offsets, chinese_remainder, sieve, Schedule, ScheduleError};
 
     use super::compute_first_valid_schedule;
 
@@ -91,24 +175,79 @@ mod tests {
     fn test_scenario() {
         assert_eq!(earliest_bus("day-13-test.txt"), (5, 59))
     }
+    #[test]
+    fn test_next_departures() {
+        let schedule = Schedule::load("day-13-test.txt");
+        let departures = schedule.next_departures(939, 3);
+        assert_eq!(departures.len(), 5);
+        assert_eq!(departures[0], (7, vec!(945, 952, 959)));
+        assert_eq!(departures[2], (59, vec!(944, 1003, 1062)));
+        assert_eq!(schedule.next_departures(945, 1)[0], (7, vec!(952)));
+    }
+
+    #[test]
+    fn test_simultaneous_departure() {
+        let schedule = Schedule::load("day-13-test.txt");
+        assert_eq!(schedule.simultaneous_departure(vec!(7, 13), 0), Ok(91));
+        assert_eq!(schedule.simultaneous_departure(vec!(7, 13), 91), Ok(182));
+        assert_eq!(schedule.simultaneous_departure(vec!(7, 13, 19), 1000), Ok(1729));
+        assert_eq!(schedule.simultaneous_departure(vec!(7, 11), 0), Err(ScheduleError::UnknownBus(11)));
+    }
+
+    #[test]
+    fn test_windows() {
+        let schedule = Schedule::load("day-13-test.txt");
+        assert_eq!(schedule.windows(vec!((7, 0), (13, 1)), 0, 3), Ok(vec!(77, 168, 259)));
+        assert_eq!(schedule.windows(vec!((13, 0), (7, 1)), 100, 1), Ok(vec!(104)));
+        assert_eq!(
+            schedule.windows(vec!((7, 0), (13, 1), (59, 3), (31, 2), (19, 1)), 0, 1),
+            Ok(vec!(1068781)));
+    }
+
     #[test]
     fn test_find_matching_pattern() {
-        assert_eq!(find_earliest_timestamp_that_matches_file("day-13-test.txt"), 1068781);
-        assert_eq!(find_earliest_timestamp_that_matches("17,x,13,19".split(',').collect()), 3417);
-        assert_eq!(find_earliest_timestamp_that_matches("67,7,59,61".split(',').collect()), 754018);
-        assert_eq!(find_earliest_timestamp_that_matches("67,x,7,59,61".split(',').collect()), 779210);
-        assert_eq!(find_earliest_timestamp_that_matches("67,7,x,59,61".split(',').collect()), 1261476);
-        assert_eq!(find_earliest_timestamp_that_matches("1789,37,47,1889".split(',').collect()), 1202161486);
+        assert_eq!(find_earliest_timestamp_that_matches_file("day-13-test.txt"), Ok(1068781));
+        assert_eq!(find_earliest_timestamp_that_matches("17,x,13,19".split(',').collect()), Ok(3417));
+        assert_eq!(find_earliest_timestamp_that_matches("67,7,59,61".split(',').collect()), Ok(754018));
+        assert_eq!(find_earliest_timestamp_that_matches("67,x,7,59,61".split(',').collect()), Ok(779210));
+        assert_eq!(find_earliest_timestamp_that_matches("67,7,x,59,61".split(',').collect()), Ok(1261476));
+        assert_eq!(find_earliest_timestamp_that_matches("1789,37,47,1889".split(',').collect()), Ok(1202161486));
+    }
+
+    #[test]
+    fn test_input_does_not_overflow() {
+        let timestamp = find_earliest_timestamp_that_matches_file("day-13-input.txt").unwrap();
+        let lines = crate::lines("day-13-input.txt").unwrap();
+        let buses = bus_offsets(lines[1].split(',').collect());
+        assert!(buses.into_iter().all(|(bus_id, offset)| (timestamp + offset as u128).is_multiple_of(bus_id as u128)));
+    }
+
+    #[test]
+    fn test_non_coprime() {
+        assert_eq!(chinese_remainder(vec!((4, 0), (6, 2))), Ok((4, 12)));
+        assert_eq!(sieve(vec!((4, 0), (6, 2))), Ok((4, 12)));
+        assert_eq!(chinese_remainder(vec!((4, 0), (6, 1))), Err(ScheduleError::Inconsistent { bus_id: 6, offset: 1, modulus: 4 }));
+        assert_eq!(sieve(vec!((4, 0), (6, 1))), Err(ScheduleError::Inconsistent { bus_id: 6, offset: 1, modulus: 4 }));
+        assert_eq!(chinese_remainder(vec!((0, 0))), Err(ScheduleError::InvalidBus(0)));
+    }
+
+    #[test]
+    fn test_sieve_matches_chinese_remainder() {
+        vec!("7,13,x,x,59,x,31,19", "17,x,13,19", "67,7,59,61", "1789,37,47,1889", "6,x,10,x,15").into_iter()
+            .for_each(|pattern| {
+                let buses = bus_offsets(pattern.split(',').collect());
+                assert_eq!(sieve(buses.clone()), chinese_remainder(buses));
+            });
     }
 
 
 
     #[test]
     fn test_theorem() {
-        assert_eq!(find_earliest_timestamp_that_matches("17,13".split(',').collect()), compute_first_valid_schedule(17, 13, 1));
-        assert_eq!(find_earliest_timestamp_that_matches("17,x,13".split(',').collect()), compute_first_valid_schedule(17, 13, 2));
-        assert_eq!(find_earliest_timestamp_that_matches("17,x,x,13".split(',').collect()), compute_first_valid_schedule(17, 13, 3));
-        assert_eq!(find_earliest_timestamp_that_matches("17,x,x,x,13".split(',').collect()), compute_first_valid_schedule(17, 13, 4));
+        assert_eq!(find_earliest_timestamp_that_matches("17,13".split(',').collect()), Ok(compute_first_valid_schedule(17, 13, 1) as u128));
+        assert_eq!(find_earliest_timestamp_that_matches("17,x,13".split(',').collect()), Ok(compute_first_valid_schedule(17, 13, 2) as u128));
+        assert_eq!(find_earliest_timestamp_that_matches("17,x,x,13".split(',').collect()), Ok(compute_first_valid_schedule(17, 13, 3) as u128));
+        assert_eq!(find_earliest_timestamp_that_matches("17,x,x,x,13".split(',').collect()), Ok(compute_first_valid_schedule(17, 13, 4) as u128));
     }
 
 
@@ -1,33 +1,59 @@
 use std::collections::HashMap;
+use std::fmt;
 #[derive(Debug, Clone)]
 enum Command {
     Set(Mask),
     Assign(Memory)
 }
+const WORD_SIZE: usize = 36;
+// Widest word whose bits and all-ones mask still fit in a usize shift.
+const MAX_WIDTH: usize = 63;
+
 #[derive(Debug, Clone)]
 struct Mask {
+    width: usize,
     mask: usize,
+    zeros: usize,
     float: Vec<usize>
 }
 
 impl Mask {
-    fn new(mask: &str) -> Mask {
+    fn new(mask: &str, width: usize) -> Result<Mask, ProgramError> {
+        if width == 0 || width > MAX_WIDTH {
+            return Err(ProgramError::Width(width));
+        }
+        if mask.len() > width || mask.chars().any(|c| !"01X".contains(c)) {
+            return Err(ProgramError::InvalidMask { mask: mask.to_string(), width });
+        }
         let reversed = mask.chars().rev().collect::<String>();
-        let mut mask = Mask { mask: 0, float: vec!() };
+        let mut mask = Mask { width, mask: 0, zeros: 0, float: vec!() };
 
-        (0..36).into_iter().for_each(|idx|
+        (0..width).for_each(|idx|
             match reversed.chars().nth(idx) {
-                Some('1') => mask.mask = mask.mask | (1 << idx),
+                Some('1') => mask.mask |= 1 << idx,
+                Some('0') => mask.zeros |= 1 << idx,
                 Some('X') => mask.float.push(idx),
                  _ => ()
             }
         );
 
-        mask
+        Ok(mask)
+    }
+
+    fn word(&self) -> usize {
+        (1 << self.width) - 1
+    }
+
+    fn floating(&self) -> usize {
+        self.float.iter().fold(0, |bits, idx| bits | (1 << idx))
+    }
+
+    fn apply_value(&self, value: usize) -> usize {
+        ((value & !self.zeros) | self.mask) & self.word()
     }
 
     fn apply(&self, number: usize) -> Vec<usize> {
-        let base = number | self.mask;
+        let base = (number | self.mask) & self.word();
         self.float.clone().into_iter().fold(vec!(base), |so_far, idx| {
             let one = 1 << idx;
             let zero = !one;
@@ -40,83 +66,443 @@ impl Mask {
     }
 }
 
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mask = (0..self.width).rev()
+            .map(|idx| {
+                let bit = 1 << idx;
+                if self.float.contains(&idx) {
+                    'X'
+                } else if self.mask & bit != 0 {
+                    '1'
+                } else {
+                    '0'
+                }
+            })
+            .collect::<String>();
+        write!(f, "{}", mask)
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Memory {
     index: usize,
     value: usize
 }
 
-impl Command {
-    fn parse(line: String) -> Result<Command, String> {
-        let parts = line.split("=").collect::<Vec<&str>>();
-        let left = parts.get(0).map(|l| l.trim());
-        let right = parts.get(1).map(|r| r.trim());
-        match left {
-            Some("mask") => right.map(Mask::new).map(|m| Command::Set(m)).ok_or("No mask defined".to_string()),
-            Some(maybeMemory) => {
-                let r = regex::Regex::new(r"mem\[([0-9]+)\]").unwrap();
-                let memory = r.captures_iter(maybeMemory)
-                    .flat_map(|s|{
-                        //this could panic, had a hard time with these regex captures
-                        s[1].parse::<usize>().into_iter()
-                    })
-                    .find(|_| true);
-                let value = right.and_then(|r| r.parse::<usize>().ok());
-                match (memory, value) {
-                    (Some(m), Some(v)) => Ok(Command::Assign(Memory { index: m, value: v})),
-                    (maybeM, maybeV) => Err(format!("Could not create Command from {}", line))
-                }
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum ProgramError {
+    Syntax { line: usize, text: String },
+    MaskWidth { line: usize, expected: usize, actual: usize },
+    ValueOverflow { line: usize, value: String },
+    AddressOverflow { line: usize, address: String },
+    WriteBeforeMask { line: usize },
+    Width(usize),
+    InvalidMask { mask: String, width: usize },
+    SumOverflow
+}
+
+struct Parser {
+    width: usize,
+    word: usize,
+    mask: regex::Regex,
+    memory: regex::Regex
+}
+
+impl Parser {
+    fn new(width: usize) -> Result<Parser, ProgramError> {
+        Mask::new("", width).map(|empty| Parser {
+            width,
+            word: empty.word(),
+            mask: regex::Regex::new(r"^mask\s*=\s*([01X]+)$").unwrap(),
+            memory: regex::Regex::new(r"^mem\[([0-9]+)\]\s*=\s*([0-9]+)$").unwrap()
+        })
+    }
+
+    fn fits(&self, number: &str) -> Option<usize> {
+        number.parse::<usize>().ok().filter(|n| *n <= self.word)
+    }
+
+    // line numbers are 1 based, comments start with '#'
+    fn parse_line(&self, line: usize, text: &str) -> Result<Option<Command>, ProgramError> {
+        let code = text.split('#').next().unwrap_or("").trim();
+        if code.is_empty() {
+            return Ok(None);
+        }
+        if let Some(captures) = self.mask.captures(code) {
+            let mask = &captures[1];
+            return match Mask::new(mask, self.width) {
+                Ok(parsed) if mask.len() == self.width => Ok(Some(Command::Set(parsed))),
+                _ => Err(ProgramError::MaskWidth { line, expected: self.width, actual: mask.len() })
+            };
+        }
+        match self.memory.captures(code) {
+            Some(captures) => {
+                let index = self.fits(&captures[1])
+                    .ok_or_else(|| ProgramError::AddressOverflow { line, address: captures[1].to_string() })?;
+                let value = self.fits(&captures[2])
+                    .ok_or_else(|| ProgramError::ValueOverflow { line, value: captures[2].to_string() })?;
+                Ok(Some(Command::Assign(Memory { index, value })))
             },
-            None => Err("empty".to_string())
+            None => Err(ProgramError::Syntax { line, text: text.to_string() })
         }
+    }
 
+    fn parse(&self, lines: Vec<String>) -> Result<Vec<Command>, Vec<ProgramError>> {
+        let (commands, errors, _) = lines.iter().enumerate()
+            .fold((vec!(), vec!(), false), |(mut commands, mut errors, masked), (idx, text)| {
+                match self.parse_line(idx + 1, text) {
+                    Ok(Some(Command::Assign(_))) if !masked => {
+                        errors.push(ProgramError::WriteBeforeMask { line: idx + 1 });
+                        (commands, errors, masked)
+                    },
+                    Ok(Some(command)) => {
+                        let masked = masked || matches!(command, Command::Set(_));
+                        commands.push(command);
+                        (commands, errors, masked)
+                    },
+                    Ok(None) => (commands, errors, masked),
+                    Err(e) => {
+                        errors.push(e);
+                        (commands, errors, masked)
+                    }
+                }
+            });
+        if errors.is_empty() {
+            Ok(commands)
+        } else {
+            Err(errors)
+        }
     }
 }
 
-fn load(file: &str) -> Vec<Command> {
-    super::lines(file).unwrap()
-        .into_iter()
-        .flat_map(|l| Command::parse(l).into_iter())
-        .collect()
+fn load(file: &str, width: usize) -> Result<Vec<Command>, Vec<ProgramError>> {
+    Parser::new(width).map_err(|e| vec!(e))?.parse(super::lines(file).unwrap())
 }
 
+const LISTED_ADDRESSES: usize = 8;
 
-pub fn run() {
-    println!("memory totals {}", count_memory("day-14-input.txt"));
+fn pretty_print(commands: &[Command], width: usize) -> Result<String, ProgramError> {
+    let printed = commands.iter()
+        .scan(Mask::new("", width)?, |mask, command| {
+            Some(match command {
+                Command::Set(new_mask) => {
+                    *mask = new_mask.clone();
+                    format!("mask = {}", new_mask)
+                },
+                Command::Assign(m) => {
+                    let pattern = AddressPattern::new(mask, m.index);
+                    let addresses = if pattern.size() <= LISTED_ADDRESSES as u128 {
+                        let mut addresses = mask.apply(m.index);
+                        addresses.sort_unstable();
+                        addresses.into_iter().map(|a| a.to_string()).collect::<Vec<String>>().join(", ")
+                    } else {
+                        pattern.describe(width)
+                    };
+                    format!("mem[{}] = {} # {} address{}: {}",
+                            m.index, m.value, pattern.size(), if pattern.size() == 1 { "" } else { "es" }, addresses)
+                }
+            })
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    Ok(printed)
+}
+
+trait Decoder {
+    fn version(&self) -> &'static str;
+    fn write(&mut self, mask: &Mask, assignment: &Memory);
+    fn sum(&self) -> Result<u128, ProgramError>;
+}
+
+// Totals are kept in u128 since 2^36 addresses holding 36 bit values already exceed a usize.
+fn total(mut values: impl Iterator<Item = Option<u128>>) -> Result<u128, ProgramError> {
+    values.try_fold(0u128, |sum, value| value.and_then(|v| sum.checked_add(v)))
+        .ok_or(ProgramError::SumOverflow)
+}
+
+#[derive(Debug, Clone, Default)]
+struct ValueDecoder {
+    memory: HashMap<usize, usize>
+}
+
+impl Decoder for ValueDecoder {
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn write(&mut self, mask: &Mask, assignment: &Memory) {
+        self.memory.insert(assignment.index, mask.apply_value(assignment.value));
+    }
+
+    fn sum(&self) -> Result<u128, ProgramError> {
+        total(self.memory.values().map(|v| Some(*v as u128)))
+    }
 }
-fn count_memory(file: &str) -> usize {
-    let mut numbers: HashMap<usize, usize> = HashMap::new();
-    load(file)
-        .into_iter()
-        .fold(Mask::new(""), |mask, command| {
+
+#[derive(Debug, Clone, Default)]
+struct AddressDecoder {
+    memory: HashMap<usize, usize>
+}
+
+impl Decoder for AddressDecoder {
+    fn version(&self) -> &'static str {
+        "v2"
+    }
+
+    fn write(&mut self, mask: &Mask, assignment: &Memory) {
+        mask.apply(assignment.index).into_iter().for_each(|addr| {
+            self.memory.insert(addr, assignment.value);
+        });
+    }
+
+    fn sum(&self) -> Result<u128, ProgramError> {
+        total(self.memory.values().map(|v| Some(*v as u128)))
+    }
+}
+
+// A ternary address: bits set in `floating` match both 0 and 1, every other bit must equal `fixed`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct AddressPattern {
+    fixed: usize,
+    floating: usize
+}
+
+impl AddressPattern {
+    fn new(mask: &Mask, address: usize) -> AddressPattern {
+        let floating = mask.floating();
+        AddressPattern {
+            fixed: (address | mask.mask) & mask.word() & !floating,
+            floating
+        }
+    }
+
+    fn size(&self) -> u128 {
+        1 << self.floating.count_ones()
+    }
+
+    fn describe(&self, width: usize) -> String {
+        (0..width).rev()
+            .map(|idx| {
+                let bit = 1 << idx;
+                if self.floating & bit != 0 {
+                    'X'
+                } else if self.fixed & bit != 0 {
+                    '1'
+                } else {
+                    '0'
+                }
+            })
+            .collect()
+    }
+
+    fn intersects(&self, other: &AddressPattern) -> bool {
+        (self.fixed ^ other.fixed) & !(self.floating | other.floating) == 0
+    }
+
+    // Splits self on every bit it floats but other fixes, keeping the halves that cannot overlap other
+    fn subtract(&self, other: &AddressPattern) -> Vec<AddressPattern> {
+        if !self.intersects(other) {
+            return vec!(*self);
+        }
+        let split_bits = self.floating & !other.floating;
+        (0..usize::BITS)
+            .map(|idx| 1 << idx)
+            .filter(|bit| split_bits & bit != 0)
+            .fold((vec!(), *self), |(mut pieces, remaining), bit| {
+                let floating = remaining.floating & !bit;
+                pieces.push(AddressPattern { fixed: remaining.fixed | (!other.fixed & bit), floating });
+                (pieces, AddressPattern { fixed: remaining.fixed | (other.fixed & bit), floating })
+            })
+            .0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct SymbolicDecoder {
+    writes: Vec<(AddressPattern, usize)>
+}
+
+impl Decoder for SymbolicDecoder {
+    fn version(&self) -> &'static str {
+        "v2"
+    }
+
+    fn write(&mut self, mask: &Mask, assignment: &Memory) {
+        let pattern = AddressPattern::new(mask, assignment.index);
+        self.writes = self.writes.iter()
+            .flat_map(|(earlier, value)| earlier.subtract(&pattern).into_iter().map(move |p| (p, *value)))
+            .collect();
+        self.writes.push((pattern, assignment.value));
+    }
+
+    fn sum(&self) -> Result<u128, ProgramError> {
+        total(self.writes.iter().map(|(pattern, value)| pattern.size().checked_mul(*value as u128)))
+    }
+}
+
+fn execute(commands: &[Command], decoder: &mut dyn Decoder, width: usize) -> Result<u128, ProgramError> {
+    commands.iter()
+        .fold(Mask::new("", width)?, |mask, command| {
             match command {
                 Command::Assign(m) => {
-                    mask.apply(m.index).into_iter().for_each(| addr| {
-                        numbers.insert(addr, m.value);
-                    });
-
+                    decoder.write(&mask, m);
                     mask
                 },
-                Command::Set(new_mask) => new_mask
+                Command::Set(new_mask) => new_mask.clone()
             }
         });
+    decoder.sum()
+}
+
+fn decoders() -> Vec<Box<dyn Decoder>> {
+    vec!(Box::new(ValueDecoder::default()), Box::new(SymbolicDecoder::default()))
+}
+
+pub fn run() {
+    let commands = match load("day-14-input.txt", WORD_SIZE) {
+        Ok(commands) => commands,
+        Err(errors) => {
+            errors.into_iter().for_each(|e| println!("{:?}", e));
+            return;
+        }
+    };
+    decoders().into_iter().for_each(|mut decoder| {
+        match execute(&commands, decoder.as_mut(), WORD_SIZE) {
+            Ok(total) => println!("{} memory totals {}", decoder.version(), total),
+            Err(e) => println!("{} memory {:?}", decoder.version(), e)
+        }
+    });
+}
 
-    numbers.into_iter().map(|(_, v)| v).sum()
+fn count_memory(file: &str, decoder: &mut dyn Decoder) -> Result<u128, Vec<ProgramError>> {
+    load(file, WORD_SIZE).and_then(|commands| execute(&commands, decoder, WORD_SIZE).map_err(|e| vec!(e)))
 }
 
 
 #[cfg(test)]
 mod test {
-    use super::Mask;
+    use super::{Mask, Memory, Decoder, ValueDecoder, AddressDecoder, SymbolicDecoder, AddressPattern, Parser, ProgramError};
+
+    fn program(lines: Vec<&str>) -> Vec<String> {
+        lines.into_iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_strict_parse() {
+        let parser = Parser::new(4).unwrap();
+        let errors = parser.parse(program(vec!(
+            "mem[1] = 2",
+            "mask = X1X",
+            "mask = X10X",
+            "",
+            "mem[16] = 1 # out of range",
+            "mem[3] = 16",
+            "mem[3] = 15",
+            "mem[3] 15"
+        ))).unwrap_err();
+        assert_eq!(errors, vec!(
+            ProgramError::WriteBeforeMask { line: 1 },
+            ProgramError::MaskWidth { line: 2, expected: 4, actual: 3 },
+            ProgramError::AddressOverflow { line: 5, address: "16".to_string() },
+            ProgramError::ValueOverflow { line: 6, value: "16".to_string() },
+            ProgramError::Syntax { line: 8, text: "mem[3] 15".to_string() }
+        ));
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        let commands = Parser::new(6).unwrap().parse(program(vec!(
+            "mask = 0X1001",
+            "mem[42]=10",
+            "mask = XXXXXX",
+            "mem[0] = 1"
+        ))).unwrap();
+        let printed = super::pretty_print(&commands, 6).unwrap();
+        assert_eq!(printed, "mask = 0X1001\n\
+            mem[42] = 10 # 2 addresses: 43, 59\n\
+            mask = XXXXXX\n\
+            mem[0] = 1 # 64 addresses: XXXXXX");
+        let reparsed = Parser::new(6).unwrap().parse(printed.lines().map(|l| l.to_string()).collect()).unwrap();
+        assert_eq!(super::pretty_print(&reparsed, 6), Ok(printed));
+    }
 
     #[test]
     fn test_count() {
-        let count = super::count_memory("day-14-test.txt");
-        assert_eq!(count, 208);
+        let count = super::count_memory("day-14-test.txt", &mut AddressDecoder::default());
+        assert_eq!(count, Ok(208));
+    }
+
+    #[test]
+    fn test_count_value_decoder() {
+        let count = super::count_memory("day-14-test-1.txt", &mut ValueDecoder::default());
+        assert_eq!(count, Ok(165));
+    }
+
+    #[test]
+    fn test_count_symbolic() {
+        assert_eq!(super::count_memory("day-14-test.txt", &mut SymbolicDecoder::default()), Ok(208));
+        assert_eq!(
+            super::count_memory("day-14-input.txt", &mut SymbolicDecoder::default()),
+            super::count_memory("day-14-input.txt", &mut AddressDecoder::default()));
+    }
+
+    #[test]
+    fn test_subtract() {
+        let mask = Mask::new("0XX", 3).unwrap();
+        let all = AddressPattern::new(&mask, 0);
+        let one = AddressPattern::new(&Mask::new("0X1", 3).unwrap(), 0);
+        let remaining = all.subtract(&one);
+        assert_eq!(remaining, vec!(AddressPattern { fixed: 0b000, floating: 0b010 }));
+        assert!(remaining.iter().all(|r| !r.intersects(&one)));
+        assert_eq!(all.subtract(&AddressPattern { fixed: 0b100, floating: 0 }), vec!(all));
+    }
+
+    #[test]
+    fn test_many_floating_bits() {
+        let mut decoder = SymbolicDecoder::default();
+        let wide = Mask::new("000000000000XXXXXXXXXXXXXXXXXXXXXXXX", super::WORD_SIZE).unwrap();
+        let narrow = Mask::new("000000000000000000000000XXXXXXXXXXXX", super::WORD_SIZE).unwrap();
+        decoder.write(&wide, &Memory { index: 0, value: 3 });
+        decoder.write(&narrow, &Memory { index: 0, value: 1 });
+        decoder.write(&Mask::new("", super::WORD_SIZE).unwrap(), &Memory { index: 1 << 30, value: 5 });
+        assert_eq!(decoder.sum(), Ok(3 * ((1 << 24) - (1 << 12)) + (1 << 12) + 5));
+    }
+
+    #[test]
+    fn test_sum_overflow() {
+        let word = (1 << super::WORD_SIZE) - 1;
+        let mut decoder = SymbolicDecoder::default();
+        decoder.write(&Mask::new(&"X".repeat(super::WORD_SIZE), super::WORD_SIZE).unwrap(), &Memory { index: 0, value: word });
+        assert_eq!(decoder.sum(), Ok((word as u128) << super::WORD_SIZE));
+        let values = ValueDecoder { memory: (0..4).map(|idx| (idx, usize::MAX)).collect() };
+        assert_eq!(values.sum(), Ok(4 * usize::MAX as u128));
     }
+
+    #[test]
+    fn test_width() {
+        let mask = Mask::new("X1X0", 4).unwrap();
+        assert_eq!(mask.apply_value(0b1111), 0b1110);
+        assert_eq!(mask.apply_value(0b10001), 0b0100);
+        let mut addresses = mask.apply(0b10001);
+        addresses.sort();
+        assert_eq!(addresses, vec!(0b0101, 0b0111, 0b1101, 0b1111));
+    }
+
+    #[test]
+    fn test_mask_validation() {
+        assert_eq!(Mask::new("", 0).err(), Some(ProgramError::Width(0)));
+        assert_eq!(Mask::new("", 64).err(), Some(ProgramError::Width(64)));
+        assert_eq!(Mask::new("X1X0", 3).err(), Some(ProgramError::InvalidMask { mask: "X1X0".to_string(), width: 3 }));
+        assert_eq!(Mask::new("X1Y", 3).err(), Some(ProgramError::InvalidMask { mask: "X1Y".to_string(), width: 3 }));
+        assert_eq!(Mask::new(&"X".repeat(63), 63).map(|m| m.floating()), Ok(usize::MAX >> 1));
+        assert!(Parser::new(64).is_err());
+        assert_eq!(super::load("day-14-test.txt", 70).err(), Some(vec!(ProgramError::Width(70))));
+    }
+
     #[test]
     fn test_load() {
-        let cmds = super::load("day-14-input.txt");
+        let cmds = super::load("day-14-input.txt", super::WORD_SIZE).unwrap();
         println!("{:?}", cmds);
         assert_eq!(cmds.len(), 569)
     }
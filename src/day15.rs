@@ -1,31 +1,134 @@
-use std::collections::HashMap;
-
-fn memory_game(target_idx: usize, initial_numbers: Vec<usize>) -> usize {
-    let mut numbers: HashMap<usize, usize> = HashMap::new();
-    let result = (0..target_idx).into_iter().fold(None, |spoken, idx| {
-        let result = initial_numbers.get(idx)
-            .map(|&i| i)
-            .or_else(|| spoken
-                .and_then(|s| numbers.get(&s))
-                .map(|prev_idx| { idx - prev_idx }))
-            .or(Some(0));
-        spoken.map(|s| {
-            numbers.insert(s, idx)
-        });
-        result
-    });
-
-    result.unwrap()
+use std::collections::{BTreeMap, HashMap};
+
+// Spoken numbers after the starting ones are gaps between turns, so only starting numbers can
+// exceed the turn count. Those are kept out of the dense table.
+const DENSE_LIMIT: usize = 1 << 20;
+// Tables for longer games grow as numbers are spoken rather than being allocated up front.
+const PRESIZE_LIMIT: usize = 1 << 27;
+
+// last_spoken[n] holds the turn (1 based) n was last spoken on before the current turn, 0 if never
+struct MemoryGame {
+    starting: Vec<u32>,
+    last_spoken: Vec<u32>,
+    dense_limit: usize,
+    sparse: HashMap<u32, u32>,
+    turn: u32,
+    previous: Option<u32>
+}
+
+impl MemoryGame {
+    fn new(starting: Vec<u32>) -> MemoryGame {
+        MemoryGame::with_capacity(starting, 0)
+    }
+
+    fn with_capacity(starting: Vec<u32>, turns: usize) -> MemoryGame {
+        MemoryGame {
+            starting,
+            last_spoken: vec!(0; turns.min(PRESIZE_LIMIT)),
+            dense_limit: turns.max(DENSE_LIMIT),
+            sparse: HashMap::new(),
+            turn: 0,
+            previous: None
+        }
+    }
+
+    fn last_spoken(&self, number: u32) -> u32 {
+        match self.last_spoken.get(number as usize) {
+            Some(last) => *last,
+            None => self.sparse.get(&number).copied().unwrap_or(0)
+        }
+    }
+
+    fn record(&mut self, number: u32, turn: u32) {
+        let idx = number as usize;
+        if idx >= self.dense_limit {
+            self.sparse.insert(number, turn);
+            return;
+        }
+        if idx >= self.last_spoken.len() {
+            self.last_spoken.resize((idx + 1).max(self.last_spoken.len() * 2).min(self.dense_limit), 0);
+        }
+        self.last_spoken[idx] = turn;
+    }
+
+    fn first_spoken(starting: Vec<u32>, number: u32, max_turns: usize) -> Option<usize> {
+        MemoryGame::new(starting)
+            .take(max_turns)
+            .position(|n| n == number)
+            .map(|idx| idx + 1)
+    }
+
+    // every number spoken after the starting sequence is the gap since it was last spoken, 0 means it was new
+    fn gap_distribution(starting: Vec<u32>, turns: usize) -> BTreeMap<u32, usize> {
+        let skip = starting.len();
+        MemoryGame::with_capacity(starting, turns)
+            .take(turns)
+            .skip(skip)
+            .filter(|gap| *gap > 0)
+            .fold(BTreeMap::new(), |mut distribution, gap| {
+                *distribution.entry(gap).or_insert(0) += 1;
+                distribution
+            })
+    }
 }
+
+impl Iterator for MemoryGame {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let turn = self.turn;
+        let spoken = match (self.starting.get(turn as usize), self.previous) {
+            (Some(n), _) => *n,
+            (None, Some(previous)) => match self.last_spoken(previous) {
+                0 => 0,
+                last => turn - last
+            },
+            (None, None) => 0
+        };
+        if let Some(previous) = self.previous {
+            self.record(previous, turn);
+        }
+        self.previous = Some(spoken);
+        self.turn = turn.checked_add(1)?;
+        Some(spoken)
+    }
+}
+
+// None when the game stops before target_idx, turns are counted in u32.
+fn memory_game(target_idx: usize, starting: Vec<u32>) -> Option<usize> {
+    MemoryGame::with_capacity(starting, target_idx)
+        .nth(target_idx.checked_sub(1)?)
+        .map(|n| n as usize)
+}
+
+fn parse_starting(numbers: &str) -> Result<Vec<u32>, String> {
+    numbers.split(',')
+        .map(|n| n.trim().parse::<u32>().map_err(|_| format!("not a starting number between 0 and {}: {}", u32::MAX, n)))
+        .collect()
+}
+
 pub fn run() {
-    println!("the result is {}", memory_game(30000000, vec!(6,4,12,1,20,0,16)))
+    run_args(&[])
+}
+
+pub fn run_args(args: &[String]) {
+    let starting = args.first().map(|a| parse_starting(a)).unwrap_or_else(|| Ok(vec!(6,4,12,1,20,0,16)));
+    let turns = args.get(1).map(|t| t.parse::<u32>().map_err(|_| format!("not a turn count between 1 and {}: {}", u32::MAX, t)))
+        .unwrap_or(Ok(30000000));
+    match (starting, turns) {
+        (Ok(starting), Ok(turns)) => match memory_game(turns as usize, starting) {
+            Some(result) => println!("the result is {}", result),
+            None => println!("the turn count must be between 1 and {}", u32::MAX)
+        },
+        (Err(e), _) | (_, Err(e)) => println!("{}", e)
+    }
 }
 #[cfg(test)]
 mod test {
-    use crate::day15::memory_game;
+    use crate::day15::{memory_game, MemoryGame};
 
-    fn test_memory_game_result(input: Vec<usize>, expected: usize) {
-        assert_eq!(memory_game(2020, input), expected)
+    fn test_memory_game_result(input: Vec<u32>, expected: usize) {
+        assert_eq!(memory_game(2020, input), Some(expected))
     }
     #[test]
     fn test_results() {
@@ -36,4 +139,40 @@ mod test {
         test_memory_game_result(vec!(3, 2, 1), 438);
         test_memory_game_result(vec!(3,1,2), 1836);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_spoken_numbers() {
+        let spoken = MemoryGame::new(vec!(0, 3, 6)).take(10).collect::<Vec<u32>>();
+        assert_eq!(spoken, vec!(0, 3, 6, 0, 3, 3, 1, 0, 4, 0));
+    }
+
+    #[test]
+    fn test_queries() {
+        assert_eq!(MemoryGame::first_spoken(vec!(0, 3, 6), 4, 100), Some(9));
+        assert_eq!(MemoryGame::first_spoken(vec!(0, 3, 6), 1000, 100), None);
+        let gaps = MemoryGame::gap_distribution(vec!(0, 3, 6), 10);
+        assert_eq!(gaps.into_iter().collect::<Vec<(u32, usize)>>(), vec!((1, 1), (3, 2), (4, 1)));
+    }
+
+    #[test]
+    fn test_parse_starting() {
+        assert_eq!(super::parse_starting("6,4, 12"), Ok(vec!(6, 4, 12)));
+        assert!(super::parse_starting("6,x").is_err());
+        assert!(super::parse_starting("5000000000,10").is_err());
+        assert_eq!(super::parse_starting("4294967295"), Ok(vec!(u32::MAX)));
+    }
+
+    #[test]
+    fn test_turn_limits() {
+        assert_eq!(memory_game(0, vec!(0, 3, 6)), None);
+        assert_eq!(memory_game(1, vec!(0, 3, 6)), Some(0));
+    }
+
+    #[test]
+    fn test_large_starting_numbers() {
+        let mut game = MemoryGame::with_capacity(vec!(u32::MAX, 7, u32::MAX), 10);
+        assert_eq!(game.by_ref().take(5).collect::<Vec<u32>>(), vec!(u32::MAX, 7, u32::MAX, 2, 0));
+        assert!(game.last_spoken.len() <= super::DENSE_LIMIT);
+        assert_eq!(game.sparse.get(&u32::MAX), Some(&3));
+    }
+}
@@ -1,5 +1,49 @@
-use regex::internal::Input;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+const AMBIGUITY_LIMIT: usize = 10;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum AssignmentError {
+    Ambiguous(Vec<HashMap<String, usize>>),
+    Impossible { fields: Vec<String>, columns: Vec<usize> }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum FieldFilter {
+    Exact(String),
+    Prefix(String),
+    Suffix(String),
+    Contains(String)
+}
+
+impl FieldFilter {
+    // "departure*", "*location", "*row*" or an exact field name
+    fn parse(filter: &str) -> FieldFilter {
+        let filter = filter.trim();
+        match (filter.strip_prefix('*'), filter.strip_suffix('*')) {
+            (Some(rest), Some(_)) if !rest.is_empty() => FieldFilter::Contains(rest[..rest.len() - 1].to_string()),
+            (Some(rest), _) => FieldFilter::Suffix(rest.to_string()),
+            (None, Some(rest)) => FieldFilter::Prefix(rest.to_string()),
+            (None, None) => FieldFilter::Exact(filter.to_string())
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            FieldFilter::Exact(n) => name == n,
+            FieldFilter::Prefix(p) => name.starts_with(p.as_str()),
+            FieldFilter::Suffix(s) => name.ends_with(s.as_str()),
+            FieldFilter::Contains(c) => name.contains(c.as_str())
+        }
+    }
+}
+
+fn product_of(fields: &HashMap<String, usize>, answers: &[usize], filters: &[FieldFilter]) -> usize {
+    fields.iter()
+        .filter(|(name, _)| filters.iter().any(|f| f.matches(name)))
+        .flat_map(|(_, idx)| answers.get(*idx))
+        .product()
+}
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 struct Field {
@@ -7,46 +51,134 @@ struct Field {
     ranges: Vec<ValidRange>
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
 struct ValidRange {
     min: usize,
     max: usize
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum DocumentError {
+    Unreadable { file: String, reason: String },
+    MissingSection(&'static str),
+    InvalidField { line: usize, text: String },
+    InvertedRange { field: String, range: ValidRange },
+    OverlappingRanges { field: String, first: ValidRange, second: ValidRange },
+    InvalidTicket { line: usize, text: String },
+    ColumnCount { line: usize, expected: usize, actual: usize },
+    UnexpectedLine { line: usize, text: String }
+}
+
 impl Field {
-    fn new(line: String) -> Option<Field> {
-        let mut parts = line.split(':');
-        let name = parts.next().map(|n| n.trim());
-        let ranges = parts.next().into_iter().flat_map(|ranges| {
-            ranges.split("or").flat_map(|r| {
+    fn parse(line: usize, text: &str) -> Result<Field, DocumentError> {
+        let invalid = || DocumentError::InvalidField { line, text: text.to_string() };
+        let mut parts = text.splitn(2, ':');
+        let name = parts.next().map(|n| n.trim()).filter(|n| !n.is_empty()).ok_or_else(invalid)?;
+        let ranges = parts.next().ok_or_else(invalid)?
+            .split(" or ")
+            .map(|r| {
                 let mut parts = r.split('-');
                 let low = parts.next().and_then(|low| low.trim().parse::<usize>().ok());
                 let high = parts.next().and_then(|high| high.trim().parse::<usize>().ok());
-                low.and_then(|l| high.map(|h| ValidRange { min: l, max: h }))
+                match (low, high, parts.next()) {
+                    (Some(min), Some(max), None) => Ok(ValidRange { min, max }),
+                    _ => Err(invalid())
+                }
             })
-        }).collect::<Vec<ValidRange>>();
-        if ranges.is_empty() {
-            None
-        } else {
-            name.map(|n| Field { name: n.to_string(), ranges: ranges })
+            .collect::<Result<Vec<ValidRange>, DocumentError>>()?;
+        let field = Field { name: name.to_string(), ranges };
+        field.validate().map(|_| field)
+    }
+
+    fn validate(&self) -> Result<(), DocumentError> {
+        if let Some(range) = self.ranges.iter().find(|r| r.min > r.max) {
+            return Err(DocumentError::InvertedRange { field: self.name.clone(), range: *range });
+        }
+        let mut sorted = self.ranges.clone();
+        sorted.sort();
+        match sorted.windows(2).find(|pair| pair[1].min <= pair[0].max) {
+            Some(pair) => Err(DocumentError::OverlappingRanges { field: self.name.clone(), first: pair[0], second: pair[1] }),
+            None => Ok(())
         }
     }
 
     fn is_valid(&self, number: usize) -> bool {
-        self.ranges.clone().into_iter().any(|r| r.max >= number && r.min <= number)
+        self.ranges.iter().any(|r| r.max >= number && r.min <= number)
     }
 }
 
-fn load_fields(file: &str) -> Vec<Field> {
-    super::lines(file).unwrap().into_iter().flat_map(Field::new).collect()
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct TicketDocument {
+    fields: Vec<Field>,
+    mine: Vec<usize>,
+    nearby: Vec<Vec<usize>>
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Section {
+    Fields,
+    Mine,
+    Nearby
 }
 
-fn load_answers(file: &str) -> Vec<Vec<usize>> {
-    super::lines(file).unwrap().into_iter()
-        .skip_while(|l| l != "nearby tickets:")
-        .map(|l| l.split(',').into_iter().flat_map(|n| n.parse::<usize>().ok()).collect::<Vec<usize>>())
-        .filter(|l| !l.is_empty())
-        .collect()
+const MY_TICKET: &str = "your ticket:";
+const NEARBY_TICKETS: &str = "nearby tickets:";
+
+impl TicketDocument {
+    fn load(file: &str) -> Result<TicketDocument, DocumentError> {
+        super::lines(file)
+            .map_err(|e| DocumentError::Unreadable { file: file.to_string(), reason: e.to_string() })
+            .and_then(TicketDocument::parse)
+    }
+
+    fn parse(lines: Vec<String>) -> Result<TicketDocument, DocumentError> {
+        let mut fields = vec!();
+        let mut mine: Option<(usize, Vec<usize>)> = None;
+        let mut nearby = vec!();
+        let mut section = Section::Fields;
+        for (idx, text) in lines.iter().enumerate() {
+            let line = idx + 1;
+            match (section, text.trim()) {
+                (_, "") => {},
+                (Section::Fields, MY_TICKET) => section = Section::Mine,
+                (Section::Mine, NEARBY_TICKETS) if mine.is_some() => section = Section::Nearby,
+                (Section::Fields, NEARBY_TICKETS) | (Section::Mine, NEARBY_TICKETS) =>
+                    return Err(DocumentError::MissingSection(MY_TICKET)),
+                (_, MY_TICKET) | (_, NEARBY_TICKETS) =>
+                    return Err(DocumentError::UnexpectedLine { line, text: text.to_string() }),
+                (Section::Fields, field) => fields.push(Field::parse(line, field)?),
+                (Section::Mine, ticket) if mine.is_none() => mine = Some((line, TicketDocument::ticket(line, ticket)?)),
+                (Section::Nearby, ticket) => nearby.push((line, TicketDocument::ticket(line, ticket)?)),
+                _ => return Err(DocumentError::UnexpectedLine { line, text: text.to_string() })
+            }
+        }
+        match section {
+            Section::Fields => return Err(DocumentError::MissingSection(MY_TICKET)),
+            Section::Mine => return Err(DocumentError::MissingSection(NEARBY_TICKETS)),
+            Section::Nearby => {}
+        }
+        let (mine_line, mine) = mine.ok_or(DocumentError::MissingSection(MY_TICKET))?;
+        let expected = fields.len();
+        let nearby = std::iter::once((mine_line, mine)).chain(nearby)
+            .map(|(line, ticket)| if ticket.len() == expected {
+                Ok(ticket)
+            } else {
+                Err(DocumentError::ColumnCount { line, expected, actual: ticket.len() })
+            })
+            .collect::<Result<Vec<Vec<usize>>, DocumentError>>()?;
+        let mut tickets = nearby.into_iter();
+        Ok(TicketDocument {
+            fields,
+            mine: tickets.next().unwrap_or_default(),
+            nearby: tickets.collect()
+        })
+    }
+
+    fn ticket(line: usize, text: &str) -> Result<Vec<usize>, DocumentError> {
+        text.split(',')
+            .map(|n| n.trim().parse::<usize>().map_err(|_| DocumentError::InvalidTicket { line, text: text.to_string() }))
+            .collect()
+    }
 }
 
 fn find_illegal_answers(fields: Vec<Field>, answers: Vec<Vec<usize>>) -> Vec<usize> {
@@ -65,24 +197,109 @@ fn only_legal_answers(fields: Vec<Field>, answers: Vec<Vec<usize>>) -> Vec<Vec<u
     ).collect()
 }
 
-fn load_my_answers(file: &str) -> Vec<usize> {
-    let mut lines = super::lines(file).unwrap().into_iter()
-        .skip_while(|l| l != "your ticket:");
-    lines.next();
-    lines.next().map(|l|
-        l.split(',').into_iter()
-            .flat_map(|a| a.parse::<usize>().ok())
-            .collect::<Vec<usize>>()
-    ).unwrap_or(vec!())
-
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct InvalidTicket {
+    ticket: usize,
+    values: Vec<(usize, usize)>
+}
 
+// compatibility[field][column] counts the valid nearby tickets whose value in that column fits the field
+#[derive(Debug, Clone, PartialEq)]
+struct Analysis {
+    columns: usize,
+    tickets: usize,
+    invalid: Vec<InvalidTicket>,
+    column_error_rates: Vec<f64>,
+    valid_tickets: usize,
+    compatibility: Vec<(String, Vec<usize>)>
 }
 
-fn part_1(file: &str) -> usize {
-    let fields = load_fields(file);
-    let answers = load_answers(file);
+impl Analysis {
+    fn new(document: &TicketDocument) -> Analysis {
+        let fields = &document.fields;
+        let columns = fields.len();
+        let invalid = document.nearby.iter().enumerate()
+            .map(|(ticket, answers)| InvalidTicket {
+                ticket,
+                values: answers.iter().cloned().enumerate()
+                    .filter(|(_, v)| !fields.iter().any(|f| f.is_valid(*v)))
+                    .collect()
+            })
+            .filter(|t| !t.values.is_empty())
+            .collect::<Vec<InvalidTicket>>();
+        let tickets = document.nearby.len();
+        let column_error_rates = (0..columns)
+            .map(|column| {
+                let errors = invalid.iter().filter(|t| t.values.iter().any(|(c, _)| *c == column)).count();
+                if tickets == 0 { 0.0 } else { errors as f64 / tickets as f64 }
+            })
+            .collect();
+        let valid = only_legal_answers(fields.clone(), document.nearby.clone());
+        let compatibility = fields.iter()
+            .map(|field| (field.name.clone(), (0..columns)
+                .map(|column| valid.iter().filter(|t| t.get(column).map(|v| field.is_valid(*v)).unwrap_or(false)).count())
+                .collect()))
+            .collect();
+        Analysis { columns, tickets, invalid, column_error_rates, valid_tickets: valid.len(), compatibility }
+    }
+
+    fn candidates(&self, counts: &[usize]) -> Vec<usize> {
+        (0..counts.len()).filter(|c| counts[*c] == self.valid_tickets).collect()
+    }
+
+    fn field_error_rate(&self, counts: &[usize]) -> f64 {
+        let values = self.valid_tickets * self.columns;
+        if values == 0 {
+            0.0
+        } else {
+            (values - counts.iter().sum::<usize>()) as f64 / values as f64
+        }
+    }
+
+    fn to_table(&self) -> String {
+        let name_width = self.compatibility.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("column".len());
+        let count_width = self.valid_tickets.to_string().len().max(self.columns.saturating_sub(1).to_string().len()).max(5);
+        let mut table = vec!(format!("{} of {} nearby tickets have invalid values", self.invalid.len(), self.tickets));
+        table.extend(self.invalid.iter().map(|t| format!("  ticket {}: {}", t.ticket, t.values.iter()
+            .map(|(column, value)| format!("{} in column {}", value, column))
+            .collect::<Vec<String>>()
+            .join(", "))));
+        table.push(String::new());
+        table.push(format!("{:<w$} | {} | error | candidates", "column",
+                           (0..self.columns).map(|c| format!("{:>w$}", c, w = count_width)).collect::<Vec<String>>().join(" "),
+                           w = name_width));
+        table.extend(self.compatibility.iter().map(|(name, counts)| format!("{:<w$} | {} | {:>4.1}% | {}",
+            name,
+            counts.iter().map(|c| format!("{:>w$}", c, w = count_width)).collect::<Vec<String>>().join(" "),
+            self.field_error_rate(counts) * 100.0,
+            self.candidates(counts).into_iter().map(|c| c.to_string()).collect::<Vec<String>>().join(" "),
+            w = name_width)));
+        table.push(format!("{:<w$} | {}", "error",
+                           self.column_error_rates.iter().map(|r| format!("{:>w$.1}%", r * 100.0, w = count_width - 1)).collect::<Vec<String>>().join(" "),
+                           w = name_width));
+        table.join("\n")
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = vec!(format!("field,{},error_rate", (0..self.columns).map(|c| format!("column_{}", c)).collect::<Vec<String>>().join(",")));
+        csv.extend(self.compatibility.iter().map(|(name, counts)| format!("{},{},{:.4}",
+            name,
+            counts.iter().map(|c| c.to_string()).collect::<Vec<String>>().join(","),
+            self.field_error_rate(counts))));
+        csv.push(format!("error_rate,{},", self.column_error_rates.iter().map(|r| format!("{:.4}", r)).collect::<Vec<String>>().join(",")));
+        csv.join("\n")
+    }
+
+    fn invalid_csv(&self) -> String {
+        let mut csv = vec!("ticket,column,value".to_string());
+        csv.extend(self.invalid.iter().flat_map(|t| t.values.iter()
+            .map(move |(column, value)| format!("{},{},{}", t.ticket, column, value))));
+        csv.join("\n")
+    }
+}
 
-    let illegals = find_illegal_answers(fields, answers);
+fn part_1(document: &TicketDocument) -> usize {
+    let illegals = find_illegal_answers(document.fields.clone(), document.nearby.clone());
 
     illegals.into_iter().sum()
 }
@@ -93,50 +310,129 @@ struct Calculator {
 }
 
 impl Calculator {
-    fn new(file: &str) -> Calculator {
+    fn new(document: &TicketDocument) -> Calculator {
         let mut calc = Calculator {
-            fields: load_fields(file),
-            answers: load_answers(file)
+            fields: document.fields.clone(),
+            answers: document.nearby.clone()
         };
 
         calc.answers = only_legal_answers(calc.fields.clone(), calc.answers.clone());
         calc
     }
 
-    fn find_fields(&self) -> HashMap<String, usize> {
-        let answer_count = self.answers.clone().into_iter().map(|a| a.len()).max().unwrap_or(0);
-
-        let possibles: HashMap<String, Vec<usize>> = self.rule_out()
+    fn candidates(&self) -> BTreeMap<String, Vec<usize>> {
+        let answer_count = self.answers.iter().map(|a| a.len()).max().unwrap_or(0);
+        self.rule_out()
             .into_iter()
             .map(|(k, v)|
-                     (k, (0..answer_count).into_iter().filter(|a| !v.contains(a)).collect())
+                     (k, (0..answer_count).filter(|a| !v.contains(a)).collect())
             )
-            .collect();
-        Calculator::answers(possibles)
+            .collect()
     }
 
-    fn answers(possibilities: HashMap<String, Vec<usize>>) -> HashMap<String, usize> {
-        if(possibilities.is_empty()) {
-            return HashMap::new();
+    fn find_fields(&self) -> Result<HashMap<String, usize>, AssignmentError> {
+        Calculator::assign(self.candidates(), AMBIGUITY_LIMIT)
+    }
+
+    fn assign(candidates: BTreeMap<String, Vec<usize>>, limit: usize) -> Result<HashMap<String, usize>, AssignmentError> {
+        let (fixed, remaining) = Calculator::propagate(candidates.clone());
+        if let Some((fields, columns)) = Calculator::hall_violation(&candidates) {
+            return Err(AssignmentError::Impossible { fields, columns });
         }
-        let mut immediate_answers: HashMap<String, usize> = possibilities.clone().into_iter()
-            .filter(|(k, v)| v.len() == 1)
-            .flat_map(|(k, v)| v.get(0).map(|a| (k, a.clone())))
-            .collect();
-        let answered: HashSet<usize> = immediate_answers.clone().into_iter().map(|(_, v)| v).collect();
-        if immediate_answers.is_empty() {
-            return HashMap::new();
-        }
-        let next_possibilities: HashMap<String, Vec<usize>> = possibilities.clone().into_iter()
-            .filter(|(k, v)| !immediate_answers.contains_key(k))
-            .map(|(k, v)| (k, v.into_iter().filter(|v|
-                !answered.contains(v)
-            ).collect::<Vec<usize>>()))
-            .collect();
+        // without a Hall violation every field fits in a distinct column, so at least one assignment exists
+        let mut assignments = vec!();
+        Calculator::enumerate(&remaining, fixed, &mut assignments, limit.max(2));
+        if assignments.len() == 1 {
+            return Ok(assignments.remove(0));
+        }
+        assignments.truncate(limit);
+        Err(AssignmentError::Ambiguous(assignments))
+    }
+
+    // Repeatedly settles fields with a single candidate column
+    fn propagate(mut candidates: BTreeMap<String, Vec<usize>>) -> (HashMap<String, usize>, BTreeMap<String, Vec<usize>>) {
+        let mut fixed: HashMap<String, usize> = HashMap::new();
+        loop {
+            let single = candidates.iter()
+                .find(|(_, columns)| columns.len() == 1)
+                .map(|(field, columns)| (field.clone(), columns[0]));
+            match single {
+                Some((field, column)) => {
+                    candidates.remove(&field);
+                    candidates.values_mut().for_each(|columns| columns.retain(|c| *c != column));
+                    fixed.insert(field, column);
+                },
+                None => return (fixed, candidates)
+            }
+        }
+    }
+
+    fn enumerate(candidates: &BTreeMap<String, Vec<usize>>, assigned: HashMap<String, usize>,
+                 found: &mut Vec<HashMap<String, usize>>, limit: usize) {
+        if found.len() >= limit {
+            return;
+        }
+        let used: HashSet<usize> = assigned.values().cloned().collect();
+        let next = candidates.iter()
+            .filter(|(field, _)| !assigned.contains_key(*field))
+            .min_by_key(|(_, columns)| columns.iter().filter(|c| !used.contains(c)).count());
+        match next {
+            None => found.push(assigned),
+            Some((field, columns)) => columns.iter()
+                .filter(|c| !used.contains(c))
+                .for_each(|column| {
+                    let mut assigned = assigned.clone();
+                    assigned.insert(field.clone(), *column);
+                    Calculator::enumerate(candidates, assigned, found, limit);
+                })
+        }
+    }
+
+    // Finds a maximum bipartite matching of fields to columns. When some field cannot be matched,
+    // the fields reachable from it by alternating paths compete for fewer columns than there are fields.
+    fn hall_violation(candidates: &BTreeMap<String, Vec<usize>>) -> Option<(Vec<String>, Vec<usize>)> {
+        let fields = candidates.keys().cloned().collect::<Vec<String>>();
+        let mut column_owner: HashMap<usize, usize> = HashMap::new();
+        fields.iter().enumerate()
+            .find(|(idx, _)| {
+                let mut visited = HashSet::new();
+                !Calculator::augment(*idx, &fields, candidates, &mut column_owner, &mut visited)
+            })
+            .map(|(idx, _)| {
+                let mut reached_fields = vec!(idx);
+                let mut reached_columns = BTreeSet::new();
+                let mut pending = vec!(idx);
+                while let Some(field) = pending.pop() {
+                    candidates[&fields[field]].iter().for_each(|column| {
+                        if reached_columns.insert(*column) {
+                            if let Some(owner) = column_owner.get(column) {
+                                reached_fields.push(*owner);
+                                pending.push(*owner);
+                            }
+                        }
+                    });
+                }
+                let mut names = reached_fields.into_iter().map(|f| fields[f].clone()).collect::<Vec<String>>();
+                names.sort();
+                (names, reached_columns.into_iter().collect())
+            })
+    }
 
-        let remainingAnswers = Calculator::answers(next_possibilities);
-        immediate_answers.extend(remainingAnswers);
-        immediate_answers
+    fn augment(field: usize, fields: &[String], candidates: &BTreeMap<String, Vec<usize>>,
+               column_owner: &mut HashMap<usize, usize>, visited: &mut HashSet<usize>) -> bool {
+        candidates[&fields[field]].iter().any(|column| {
+            if !visited.insert(*column) {
+                return false;
+            }
+            let free = match column_owner.get(column) {
+                None => true,
+                Some(owner) => Calculator::augment(*owner, fields, candidates, column_owner, visited)
+            };
+            if free {
+                column_owner.insert(*column, field);
+            }
+            free
+        })
     }
 
     fn rule_out(&self) -> HashMap<String, HashSet<usize>> {
@@ -162,51 +458,228 @@ impl Calculator {
         result
     }
 }
-pub fn run() {
-    println!("sum of illegal answers is {}", part_1("day-16-input.txt"));
-    let calculator = Calculator::new("day-16-input.txt");
-    let fields = calculator.find_fields();
-    println!("fields {:?}", fields);
-
-
+const DEFAULT_FILTER: &str = "departure*";
+
+// day16 [report|csv|invalid [file]] or day16 product [filter,filter,...] [file]
+pub fn run_args(args: &[String]) {
+    if args.first().map(|a| a.as_str()) == Some("product") {
+        let filters = args.get(1).map(|f| f.as_str()).unwrap_or(DEFAULT_FILTER)
+            .split(',')
+            .map(FieldFilter::parse)
+            .collect::<Vec<FieldFilter>>();
+        return product(args.get(2).map(|f| f.as_str()).unwrap_or("day-16-input.txt"), &filters);
+    }
+    let file = args.get(1).map(|f| f.as_str()).unwrap_or("day-16-input.txt");
+    let analysis = match TicketDocument::load(file) {
+        Ok(document) => Analysis::new(&document),
+        Err(e) => {
+            println!("could not read tickets: {:?}", e);
+            return;
+        }
+    };
+    match args.first().map(|a| a.as_str()) {
+        Some("report") => println!("{}", analysis.to_table()),
+        Some("csv") => println!("{}", analysis.to_csv()),
+        Some("invalid") => println!("{}", analysis.invalid_csv()),
+        _ => run()
+    }
+}
 
-    let my_answers = load_my_answers("day-16-input.txt");
-    println!("my answers {:?}", my_answers);
+pub fn run() {
+    product("day-16-input.txt", &[FieldFilter::parse(DEFAULT_FILTER)])
+}
 
-    let result = fields.into_iter()
-        .filter(|(name, idx)| name.starts_with("departure"))
-        .flat_map(|(_, idx)| my_answers.get(idx))
-        .fold(1, |prod, next| prod * next);
-    println!("The product of duration fields is {}", result);
+fn product(file: &str, filters: &[FieldFilter]) {
+    let document = match TicketDocument::load(file) {
+        Ok(document) => document,
+        Err(e) => {
+            println!("could not read tickets: {:?}", e);
+            return;
+        }
+    };
+    println!("sum of illegal answers is {}", part_1(&document));
+    let calculator = Calculator::new(&document);
+    let fields = match calculator.find_fields() {
+        Ok(fields) => fields,
+        Err(AssignmentError::Ambiguous(assignments)) => {
+            println!("fields are ambiguous, found {} possible assignments:", assignments.len());
+            assignments.into_iter().for_each(|a| println!("  {:?}", a));
+            return;
+        },
+        Err(AssignmentError::Impossible { fields, columns }) => {
+            println!("fields {:?} can only be placed in columns {:?}", fields, columns);
+            return;
+        }
+    };
+    println!("fields {:?}", fields);
+    println!("my answers {:?}", document.mine);
+    let result = product_of(&fields, &document.mine, filters);
+    println!("The product of {:?} fields is {}", filters, result);
 }
 #[cfg(test)]
 mod test {
-    use crate::day16::{part_1};
-    use std::collections::HashMap;
+    use crate::day16::{part_1, Analysis, InvalidTicket, AssignmentError, Calculator, DocumentError, FieldFilter, TicketDocument, ValidRange};
+    use std::collections::{BTreeMap, HashMap};
+
+    fn candidates(fields: Vec<(&str, Vec<usize>)>) -> BTreeMap<String, Vec<usize>> {
+        fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+    }
 
     #[test]
     fn test_sum_illegal_answers() {
-        let sum = part_1("day-16-test.txt");
+        let sum = part_1(&TicketDocument::load("day-16-test.txt").unwrap());
 
         assert_eq!(sum, 71)
     }
 
+    fn parse_document(lines: Vec<&str>) -> Result<TicketDocument, DocumentError> {
+        TicketDocument::parse(lines.into_iter().map(|l| l.to_string()).collect())
+    }
+
+    #[test]
+    fn test_document() {
+        let document = TicketDocument::load("day-16-test.txt").unwrap();
+        assert_eq!(document.fields.len(), 3);
+        assert_eq!(document.fields[1].ranges, vec!(ValidRange { min: 6, max: 11 }, ValidRange { min: 33, max: 44 }));
+        assert_eq!(document.mine, vec!(7, 1, 14));
+        assert_eq!(document.nearby.len(), 4);
+
+        let many_ranges = parse_document(vec!("a: 1-2 or 4-5 or 7-9", "", "your ticket:", "3", "", "nearby tickets:", "8")).unwrap();
+        assert!(many_ranges.fields[0].is_valid(8));
+        assert!(!many_ranges.fields[0].is_valid(6));
+    }
+
+    #[test]
+    fn test_document_errors() {
+        assert_eq!(parse_document(vec!("a: 1-2", "", "nearby tickets:", "1")),
+                   Err(DocumentError::MissingSection("your ticket:")));
+        assert_eq!(parse_document(vec!("a: 1-2", "your ticket:", "nearby tickets:", "1")),
+                   Err(DocumentError::MissingSection("your ticket:")));
+        assert_eq!(parse_document(vec!("a: 1-2", "your ticket:", "1", "nearby tickets:", "1", "your ticket:")),
+                   Err(DocumentError::UnexpectedLine { line: 6, text: "your ticket:".to_string() }));
+        assert_eq!(parse_document(vec!("a: 1-2", "", "your ticket:", "1")),
+                   Err(DocumentError::MissingSection("nearby tickets:")));
+        assert_eq!(parse_document(vec!("a: 1-2")),
+                   Err(DocumentError::MissingSection("your ticket:")));
+        assert_eq!(parse_document(vec!("a: 1-2", "your ticket:", "1", "nearby tickets:", "1,2")),
+                   Err(DocumentError::ColumnCount { line: 5, expected: 1, actual: 2 }));
+        assert_eq!(parse_document(vec!("a: 1-2", "your ticket:", "1", "2")),
+                   Err(DocumentError::UnexpectedLine { line: 4, text: "2".to_string() }));
+        assert_eq!(parse_document(vec!("a: 1-2", "your ticket:", "1", "nearby tickets:", "x")),
+                   Err(DocumentError::InvalidTicket { line: 5, text: "x".to_string() }));
+        assert_eq!(parse_document(vec!("a: 3-2")),
+                   Err(DocumentError::InvertedRange { field: "a".to_string(), range: ValidRange { min: 3, max: 2 } }));
+        assert_eq!(parse_document(vec!("a: 5-9 or 1-5")),
+                   Err(DocumentError::OverlappingRanges { field: "a".to_string(), first: ValidRange { min: 1, max: 5 }, second: ValidRange { min: 5, max: 9 } }));
+        assert!(matches!(TicketDocument::load("day-16-missing.txt"),
+                         Err(DocumentError::Unreadable { file, .. }) if file == "day-16-missing.txt"));
+    }
+
+    #[test]
+    fn test_analysis() {
+        let analysis = Analysis::new(&TicketDocument::load("day-16-test.txt").unwrap());
+        assert_eq!(analysis.invalid, vec!(
+            InvalidTicket { ticket: 1, values: vec!((1, 4)) },
+            InvalidTicket { ticket: 2, values: vec!((0, 55)) },
+            InvalidTicket { ticket: 3, values: vec!((2, 12)) }
+        ));
+        assert_eq!(analysis.column_error_rates, vec!(0.25, 0.25, 0.25));
+        assert_eq!(analysis.valid_tickets, 1);
+        assert_eq!(analysis.compatibility, vec!(
+            ("class".to_string(), vec!(1, 1, 0)),
+            ("row".to_string(), vec!(1, 0, 0)),
+            ("seat".to_string(), vec!(0, 0, 1))
+        ));
+        assert_eq!(analysis.to_csv(), "field,column_0,column_1,column_2,error_rate\n\
+            class,1,1,0,0.3333\n\
+            row,1,0,0,0.6667\n\
+            seat,0,0,1,0.6667\n\
+            error_rate,0.2500,0.2500,0.2500,");
+        assert_eq!(analysis.invalid_csv(), "ticket,column,value\n1,1,4\n2,0,55\n3,2,12");
+    }
+
+    #[test]
+    fn test_analysis_table() {
+        let analysis = Analysis::new(&TicketDocument::load("day-16-test-2.txt").unwrap());
+        assert_eq!(analysis.to_table(), "0 of 3 nearby tickets have invalid values\n\
+            \n\
+            column |     0     1     2 | error | candidates\n\
+            class  |     2     3     3 | 11.1% | 1 2\n\
+            row    |     3     3     3 |  0.0% | 0 1 2\n\
+            seat   |     2     2     3 | 22.2% | 2\n\
+            error  |  0.0%  0.0%  0.0%");
+    }
+
     #[test]
     fn test_only_legal() {
-        let fields = super::load_fields("day-16-test.txt");
-        let answers = super::load_answers("day-16-test.txt");
-        let legals = super::only_legal_answers(fields, answers);
+        let document = TicketDocument::load("day-16-test.txt").unwrap();
+        let legals = super::only_legal_answers(document.fields, document.nearby);
         assert_eq!(legals, vec!(vec!(7, 3, 47)))
     }
 
     #[test]
     fn find_survey_structure() {
-        let calculator = super::Calculator::new("day-16-test-2.txt");
+        let calculator = super::Calculator::new(&TicketDocument::load("day-16-test-2.txt").unwrap());
         let result = calculator.find_fields();
         let expected: HashMap<String, usize> = vec!(("class", 1), ("row", 0), ("seat", 2)).into_iter()
             .map(|(k, v)| (k.to_string(), v))
             .collect();
-        assert_eq!(result, expected)
+        assert_eq!(result, Ok(expected))
+    }
+
+    #[test]
+    fn test_ambiguous_assignment() {
+        let result = Calculator::assign(candidates(vec!(("a", vec!(0, 1)), ("b", vec!(0, 1, 2)), ("c", vec!(1, 2)), ("d", vec!(3, 4)), ("e", vec!(4)))), 10);
+        let mut assignments = match result {
+            Err(AssignmentError::Ambiguous(assignments)) => assignments,
+            other => panic!("expected ambiguity, got {:?}", other)
+        };
+        assignments.sort_by_key(|a| (a["a"], a["b"]));
+        let found = assignments.into_iter()
+            .map(|a| vec!(a["a"], a["b"], a["c"], a["d"], a["e"]))
+            .collect::<Vec<Vec<usize>>>();
+        assert_eq!(found, vec!(vec!(0, 1, 2, 3, 4), vec!(0, 2, 1, 3, 4), vec!(1, 0, 2, 3, 4)));
+    }
+
+    #[test]
+    fn test_assign_with_spare_columns() {
+        let result = Calculator::assign(candidates(vec!(("a", vec!(0, 1)), ("b", vec!(1)), ("c", vec!(0, 2, 3)), ("d", vec!(3)))), 10);
+        let expected: HashMap<String, usize> = vec!(("a", 0), ("b", 1), ("c", 2), ("d", 3)).into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        assert_eq!(result, Ok(expected));
+    }
+
+    #[test]
+    fn test_ambiguity_limit() {
+        let result = Calculator::assign(candidates(vec!(("a", vec!(0, 1, 2)), ("b", vec!(0, 1, 2)), ("c", vec!(0, 1, 2)))), 4);
+        match result {
+            Err(AssignmentError::Ambiguous(assignments)) => assert_eq!(assignments.len(), 4),
+            other => panic!("expected ambiguity, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_impossible_assignment() {
+        let result = Calculator::assign(candidates(vec!(("a", vec!(0)), ("b", vec!(0)), ("c", vec!(0, 1, 2)))), 10);
+        assert_eq!(result, Err(AssignmentError::Impossible { fields: vec!("a".to_string(), "b".to_string()), columns: vec!(0) }));
+
+        let result = Calculator::assign(candidates(vec!(("a", vec!(0, 1)), ("b", vec!()))), 10);
+        assert_eq!(result, Err(AssignmentError::Impossible { fields: vec!("b".to_string()), columns: vec!() }));
+    }
+
+    #[test]
+    fn test_field_filters() {
+        assert_eq!(FieldFilter::parse("departure*"), FieldFilter::Prefix("departure".to_string()));
+        assert_eq!(FieldFilter::parse("*station"), FieldFilter::Suffix("station".to_string()));
+        assert_eq!(FieldFilter::parse("*track*"), FieldFilter::Contains("track".to_string()));
+        assert_eq!(FieldFilter::parse("row"), FieldFilter::Exact("row".to_string()));
+        let fields: HashMap<String, usize> = vec!(("class", 1), ("row", 0), ("seat", 2)).into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        let answers = vec!(11, 12, 13);
+        assert_eq!(super::product_of(&fields, &answers, &[FieldFilter::parse("row"), FieldFilter::parse("*eat")]), 143);
+        assert_eq!(super::product_of(&fields, &answers, &[FieldFilter::parse("*as*")]), 12);
     }
 
 }
\ No newline at end of file
@@ -1,32 +1,73 @@
-use std::collections::HashMap;
-use crate::day17::State::Active;
-
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-struct Coordinate {
-    x: isize,
-    y: isize,
-    z: isize,
-    w: isize
-}
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct Coordinate<const N: usize>([isize; N]);
+
+impl<const N: usize> Coordinate<N> {
+    fn origin() -> Coordinate<N> {
+        Coordinate([0; N])
+    }
+
+    fn of_xy(x: isize, y: isize) -> Coordinate<N> {
+        let mut coordinate = Coordinate::origin();
+        coordinate.0[0] = x;
+        coordinate.0[1] = y;
+        coordinate
+    }
+
+    fn shift(&self, offset: &[isize; N]) -> Coordinate<N> {
+        let mut shifted = *self;
+        (0..N).for_each(|d| shifted.0[d] += offset[d]);
+        shifted
+    }
+
+    // every coordinate in the box from min to max inclusive, the last dimension varying fastest
+    fn cube(min: &Coordinate<N>, max: &Coordinate<N>) -> Vec<Coordinate<N>> {
+        (0..N).fold(vec!(Coordinate::origin()), |partials, d|
+            partials.into_iter()
+                .flat_map(|partial| (min.0[d]..=max.0[d]).map(move |v| {
+                    let mut next = partial;
+                    next.0[d] = v;
+                    next
+                }))
+                .collect())
+    }
+
+    // the seed is a single x/y plane, so every later generation is mirror symmetric in the other dimensions
+    fn is_canonical(&self) -> bool {
+        self.0[2..].iter().all(|v| *v >= 0)
+    }
 
-impl Coordinate {
-    fn of(x: isize, y: isize, z: isize, w: isize) -> Coordinate {
-        Coordinate { x: x, y: y, z: z, w: w }
+    fn canonical(&self) -> Coordinate<N> {
+        let mut canonical = *self;
+        (2..N).for_each(|d| canonical.0[d] = canonical.0[d].abs());
+        canonical
     }
-    fn origin() -> Coordinate {
-        Coordinate { x: 0, y: 0, z: 0, w: 0 }
+
+    // number of coordinates in the full space this canonical coordinate stands for
+    fn multiplicity(&self) -> usize {
+        1 << self.0[2..].iter().filter(|v| **v != 0).count()
+    }
+
+    // Mirror images with a neighbor in the canonical region: only a coordinate of 1 can be
+    // flipped to -1 and still be adjacent to a coordinate of 0.
+    fn mirror_images(&self) -> Vec<Coordinate<N>> {
+        (2..N).filter(|d| self.0[*d] == 1)
+            .fold(vec!(*self), |images, d| images.into_iter()
+                .flat_map(|image| {
+                    let mut mirrored = image;
+                    mirrored.0[d] = -1;
+                    vec!(image, mirrored)
+                })
+                .collect())
     }
 
-    fn neighbors(&self) -> Vec<Coordinate> {
-        (self.x - 1..=self.x+1).into_iter().flat_map(move |x|
-            (self.y - 1..=self.y+1).into_iter().flat_map(move |y|
-                (self.z - 1..=self.z+1).into_iter().flat_map(move |z|
-                    (self.w - 1..=self.w+1).into_iter().flat_map(move |w|
-                    if x==self.x && y == self.y && z == self.z && w == self.w{
-                        None
-                    } else {
-                        Some(Coordinate::of(x, y, z, w))
-                    }))))
+    fn neighbors(&self) -> Vec<Coordinate<N>> {
+        let min = self.shift(&[-1; N]);
+        let max = self.shift(&[1; N]);
+        Coordinate::cube(&min, &max).into_iter()
+            .filter(|c| c != self)
             .collect()
     }
 }
@@ -46,146 +87,334 @@ impl State {
     }
 }
 
+// Life-like rules in B/S notation, the puzzle uses "B3/S23"
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Rules {
+    birth: Vec<usize>,
+    survive: Vec<usize>
+}
+
+impl Rules {
+    fn conway() -> Rules {
+        Rules { birth: vec!(3), survive: vec!(2, 3) }
+    }
+
+    fn parse(rules: &str) -> Result<Rules, String> {
+        let counts = |part: &str, prefix: char| part.trim()
+            .strip_prefix(prefix)
+            .ok_or(format!("expected {} in rules {}", prefix, rules))
+            .and_then(|counts| Rules::counts(counts)
+                .map_err(|c| format!("illegal count {} in rules {}", c, rules)));
+        let mut parts = rules.split('/');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(birth), Some(survive), None) => {
+                let birth = counts(birth, 'B')?;
+                if birth.contains(&0) {
+                    return Err(format!("B0 would activate the whole infinite space, got {}", rules));
+                }
+                Ok(Rules { birth, survive: counts(survive, 'S')? })
+            },
+            _ => Err(format!("rules must look like B3/S23 or B3,10/S2,3,12, got {}", rules))
+        }
+    }
+
+    // 4 and more dimensions have more than 9 neighbors, so counts can also be separated by commas (B3,10/S2,3,12)
+    fn counts(counts: &str) -> Result<Vec<usize>, String> {
+        if counts.contains(',') {
+            counts.split(',')
+                .map(|c| c.trim().parse::<usize>().map_err(|_| c.to_string()))
+                .collect()
+        } else {
+            counts.chars()
+                .map(|c| c.to_digit(10).map(|d| d as usize).ok_or_else(|| c.to_string()))
+                .collect()
+        }
+    }
+
+    fn next_state(&self, state: &State, active_neighbors: usize) -> State {
+        let stays_active = match state {
+            State::Active => self.survive.contains(&active_neighbors),
+            State::Inactive => self.birth.contains(&active_neighbors)
+        };
+        if stays_active {
+            State::Active
+        } else {
+            State::Inactive
+        }
+    }
+}
+
+// Only active coordinates in the canonical region (see Coordinate::is_canonical) are stored,
+// min and max are the bounds of the full space.
 #[derive(Debug, Clone, Eq, PartialEq)]
-struct Space {
-    min: Coordinate,
-    max: Coordinate,
-    xyz: HashMap<Coordinate, State>
+struct Space<const N: usize> {
+    min: Coordinate<N>,
+    max: Coordinate<N>,
+    rules: Rules,
+    active: HashSet<Coordinate<N>>
 }
 
-impl Space {
-    fn from(data: &str) -> Space {
-        let z = 0;
-        let w = 0;
+impl<const N: usize> Space<N> {
+    fn from(data: &str) -> Space<N> {
+        Space::with_rules(data, Rules::conway())
+    }
+
+    fn with_rules(data: &str, rules: Rules) -> Space<N> {
         let parts = data.split('\n').collect::<Vec<&str>>();
         let y_max = parts.len() - 1;
-        let x_max = parts.clone().into_iter().map(|p| p.len()).max().unwrap_or(1) - 1;
+        let x_max = parts.iter().map(|p| p.len()).max().unwrap_or(1) - 1;
 
         Space {
-            min: Coordinate::of(0, 0, z, w),
-            max: Coordinate::of(x_max as isize, y_max as isize, z, w),
-            xyz: (0..=y_max).into_iter().flat_map(move |y| {
-                let line = parts.get(y).map(|l| l.clone()).unwrap_or("");
-                (0..=x_max).into_iter().map(move |x|
-                    (Coordinate::of(x as isize, y as isize, z, w), match line.chars().nth(x as usize) {
-                        Some('#') => State::Active,
-                        _ => State::Inactive
-                    })
-                )
+            min: Coordinate::origin(),
+            max: Coordinate::of_xy(x_max as isize, y_max as isize),
+            rules,
+            active: (0..=y_max).flat_map(move |y| {
+                let line = parts.get(y).copied().unwrap_or("");
+                line.chars().enumerate()
+                    .filter(|(_, c)| *c == '#')
+                    .map(move |(x, _)| Coordinate::of_xy(x as isize, y as isize))
             }).collect()
         }
 
 
     }
-    fn string_rep(&self) -> Vec<Vec<String>> {
-        (self.min.w..=self.max.w).into_iter()
-            .map(|w| (self.min.z..=self.max.z).into_iter()
-                .map(|z| (self.min.y ..=self.max.y).into_iter()
-                    .map(|y| (self.min.x..=self.max.x).into_iter()
-                        .map(|x| self.state_of(Coordinate::of(x, y, z, w)).to_char())
-                        .collect::<String>())
-                    .collect::<Vec<String>>()
-                    .join("\n"))
-                .collect())
+
+    fn active(&self) -> usize {
+        self.active.iter().map(|c| c.multiplicity()).sum()
+    }
+
+    // one string per x/y plane, keyed by the coordinates of the remaining dimensions with z varying fastest
+    fn string_rep(&self) -> Vec<(Vec<isize>, String)> {
+        let mut plane_min = self.min;
+        let mut plane_max = self.max;
+        (0..2).for_each(|d| {
+            plane_min.0[d] = 0;
+            plane_max.0[d] = 0;
+        });
+        let mut planes = Coordinate::cube(&plane_min, &plane_max);
+        planes.sort_by_key(|plane| plane.0.iter().rev().cloned().collect::<Vec<isize>>());
+        planes.into_iter()
+            .map(|plane| (plane.0[2..].to_vec(), (self.min.0[1]..=self.max.0[1])
+                .map(|y| (self.min.0[0]..=self.max.0[0])
+                    .map(|x| {
+                        let mut coordinate = plane;
+                        coordinate.0[0] = x;
+                        coordinate.0[1] = y;
+                        self.state_of(coordinate).to_char()
+                    })
+                    .collect::<String>())
+                .collect::<Vec<String>>()
+                .join("\n")))
             .collect()
     }
 
-    fn next(&self) -> Space {
-        let coordinates: HashMap<Coordinate, State> = (self.min.x - 1..=self.max.x + 1).into_iter()
-            .flat_map(move |x| (self.min.y - 1..=self.max.y + 1).into_iter().flat_map(move |y|
-                (self.min.z - 1..=self.max.z + 1).into_iter().flat_map(move |z|
-                    (self.min.w - 1..=self.max.w + 1).into_iter().map(move |w|
-                    (Coordinate::of(x, y, z, w), self.next_state(Coordinate::of(x, y, z, w), self.state_of(Coordinate::of(x, y, z, w))))
-                ))))
-            .filter(|(_, state)| *state == State:: Active)
-            .collect();
-
-        let x_min = coordinates.clone().into_iter()
-            .map(|(c, _)| c.x)
-            .min().unwrap_or(0);
-        let x_max = coordinates.clone().into_iter()
-            .map(|(c, _)| c.x)
-            .max().unwrap_or(0);
-
-        let y_min = coordinates.clone().into_iter()
-            .map(|(c, _)| c.y)
-            .min().unwrap_or(0);
-        let y_max = coordinates.clone().into_iter()
-            .map(|(c, _)| c.y)
-            .max().unwrap_or(0);
-
-        let z_min = coordinates.clone().into_iter()
-            .map(|(c, _)| c.z)
-            .min().unwrap_or(0);
-        let z_max = coordinates.clone().into_iter()
-            .map(|(c, _)| c.z)
-            .max().unwrap_or(0);
-
-        let w_min = coordinates.clone().into_iter()
-            .map(|(c, _)| c.w)
-            .min().unwrap_or(0);
-        let w_max = coordinates.clone().into_iter()
-            .map(|(c, _)| c.w)
-            .max().unwrap_or(0);
+    fn next(&self) -> Space<N> {
+        let offsets = Coordinate::<N>::origin().neighbors();
+        let mut counts: HashMap<Coordinate<N>, usize> = HashMap::new();
+        self.active.iter()
+            .flat_map(|c| c.mirror_images())
+            .for_each(|image| offsets.iter()
+                .map(|offset| image.shift(&offset.0))
+                .filter(|neighbor| neighbor.is_canonical())
+                .for_each(|neighbor| *counts.entry(neighbor).or_insert(0) += 1));
+
+        let isolated = self.active.iter()
+            .filter(|c| !counts.contains_key(c))
+            .map(|c| (*c, 0))
+            .collect::<Vec<(Coordinate<N>, usize)>>();
+
+        let mut min = Coordinate::origin();
+        let mut max = Coordinate::origin();
+        let mut first = true;
+        let active = counts.into_iter()
+            .chain(isolated)
+            .filter(|(c, count)| self.rules.next_state(&self.state_of(*c), *count) == State::Active)
+            .map(|(c, _)| {
+                (0..N).for_each(|d| {
+                    min.0[d] = if first { c.0[d] } else { min.0[d].min(c.0[d]) };
+                    max.0[d] = if first { c.0[d] } else { max.0[d].max(c.0[d]) };
+                });
+                first = false;
+                c
+            })
+            .collect::<HashSet<Coordinate<N>>>();
+        (2..N).for_each(|d| min.0[d] = -max.0[d]);
 
         Space {
-            min: Coordinate::of(x_min, y_min, z_min, w_min),
-            max: Coordinate::of(x_max, y_max, z_max, w_max),
-            xyz: coordinates
+            min,
+            max,
+            rules: self.rules.clone(),
+            active
         }
     }
 
-    fn state_of(&self, coordinate: Coordinate) -> State {
-        self.xyz.get(&coordinate).map(|c| c.clone()).unwrap_or(State::Inactive)
-    }
-    fn next_state(&self, coordinate: Coordinate, state: State) -> State {
-        match state {
-            State::Active => {
-                let count = coordinate.neighbors()
-                    .into_iter()
-                    .map(|c| self.state_of(c))
-                    .filter(|s| *s == State::Active)
-                    .count();
-                if count >= 2 && count <= 3 {
-                    State::Active
-                } else {
-                    State::Inactive
-                }
-
-            },
-            State::Inactive => {
-                let count = coordinate.neighbors()
-                    .into_iter()
-                    .map(|c| self.state_of(c))
-                    .filter(|s| *s == State::Active)
-                    .count();
-                if count == 3 {
-                    State:: Active
-                } else {
-                    State::Inactive
-                }
-            }
+    fn state_of(&self, coordinate: Coordinate<N>) -> State {
+        if self.active.contains(&coordinate.canonical()) {
+            State::Active
+        } else {
+            State::Inactive
         }
     }
 }
 
+const DIMENSION_NAMES: [&str; 3] = ["z", "w", "v"];
+
+fn dimension_name(d: usize) -> String {
+    DIMENSION_NAMES.get(d - 2).map(|n| n.to_string()).unwrap_or_else(|| format!("d{}", d))
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ImageFormat {
+    Pbm,
+    Ppm
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Mode {
+    Count,
+    Render { non_empty: bool },
+    Export(ImageFormat)
+}
+
+impl<const N: usize> Space<N> {
+    // the labels and planes shown in the puzzle, e.g. "z=0, w=1"
+    fn slices(&self, non_empty: bool) -> Vec<(String, String)> {
+        self.string_rep().into_iter()
+            .filter(|(_, plane)| !non_empty || plane.contains(State::Active.to_char()))
+            .map(|(position, plane)| (position.iter().enumerate()
+                .map(|(idx, v)| format!("{}={}", dimension_name(idx + 2), v))
+                .collect::<Vec<String>>()
+                .join(", "), plane))
+            .collect()
+    }
+
+    fn render(&self, non_empty: bool) -> String {
+        self.slices(non_empty).into_iter()
+            .map(|(label, plane)| if label.is_empty() { plane } else { format!("{}\n{}", label, plane) })
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    }
+
+    fn image(plane: &str, format: ImageFormat) -> String {
+        let rows = plane.lines().collect::<Vec<&str>>();
+        let width = rows.first().map(|r| r.len()).unwrap_or(0);
+        let active = State::Active.to_char();
+        let (header, pixel): (String, Box<dyn Fn(char) -> &'static str>) = match format {
+            ImageFormat::Pbm => (format!("P1\n{} {}", width, rows.len()),
+                                 Box::new(move |c| if c == active { "1" } else { "0" })),
+            ImageFormat::Ppm => (format!("P3\n{} {}\n255", width, rows.len()),
+                                 Box::new(move |c| if c == active { "255 200 0" } else { "20 20 60" }))
+        };
+        let mut image = vec!(header);
+        image.extend(rows.into_iter().map(|row| row.chars().map(&pixel).collect::<Vec<&str>>().join(" ")));
+        image.push(String::new());
+        image.join("\n")
+    }
+
+    fn export(&self, generation: usize, prefix: &str, format: ImageFormat) -> Result<Vec<String>, String> {
+        let extension = match format {
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Ppm => "ppm"
+        };
+        self.slices(false).into_iter()
+            .map(|(label, plane)| {
+                let name = format!("{}-gen{}{}.{}", prefix, generation,
+                                   label.split(", ").filter(|l| !l.is_empty()).map(|l| format!("-{}", l.replace('=', ""))).collect::<String>(),
+                                   extension);
+                fs::write(&name, Self::image(&plane, format)).map(|_| name).map_err(|e| e.to_string())
+            })
+            .collect()
+    }
+}
+
+fn generation_header(generation: usize) -> String {
+    match generation {
+        0 => "Before any cycles:".to_string(),
+        1 => "After 1 cycle:".to_string(),
+        n => format!("After {} cycles:", n)
+    }
+}
+
+fn generations<const N: usize>(data: &str, rules: Rules, cycles: usize) -> Vec<Space<N>> {
+    let space: Space<N> = Space::with_rules(data, rules);
+    (0..cycles).fold(vec!(space), |mut spaces, _| {
+        let next = spaces.last().map(|s| s.next());
+        spaces.extend(next);
+        spaces
+    })
+}
+
+fn render<const N: usize>(data: &str, rules: Rules, cycles: usize, non_empty: bool) -> String {
+    generations::<N>(data, rules, cycles).into_iter().enumerate()
+        .map(|(generation, space)| format!("{}\n\n{}", generation_header(generation), space.render(non_empty)))
+        .collect::<Vec<String>>()
+        .join("\n\n\n")
+}
+
+fn simulate<const N: usize>(data: &str, rules: Rules, cycles: usize) -> usize {
+    let space: Space<N> = Space::with_rules(data, rules);
+    (0..cycles).fold(space, |s, _| s.next()).active()
+}
+
+fn execute<const N: usize>(data: &str, rules: Rules, cycles: usize, mode: Mode) {
+    match mode {
+        Mode::Count => println!("final space has {} active spaces", simulate::<N>(data, rules, cycles)),
+        Mode::Render { non_empty } => println!("{}", render::<N>(data, rules, cycles, non_empty)),
+        Mode::Export(format) => generations::<N>(data, rules, cycles).into_iter().enumerate()
+            .for_each(|(generation, space)| match space.export(generation, "day-17-slice", format) {
+                Ok(files) => println!("{} wrote {} slices", generation_header(generation), files.len()),
+                Err(e) => println!("{} {}", generation_header(generation), e)
+            })
+    }
+}
+
 pub fn run() {
     let data = super::lines("day-17-input.txt")
         .unwrap()
         .join("\n");
 
-    let space = Space::from(data.as_str());
-    let final_space = (0..6).into_iter().fold(space, |s, _| s.next());
-    println!("final space has {} active spaces", final_space.xyz.len());
+    println!("final space has {} active spaces", simulate::<4>(data.as_str(), Rules::conway(), 6));
+}
+
+// day17 [render|render-active|pbm|ppm] [dimensions] [rules] [cycles] [file]
+pub fn run_args(args: &[String]) {
+    let (mode, args) = match args.first().map(|a| a.as_str()) {
+        Some("render") => (Mode::Render { non_empty: false }, &args[1..]),
+        Some("render-active") => (Mode::Render { non_empty: true }, &args[1..]),
+        Some("pbm") => (Mode::Export(ImageFormat::Pbm), &args[1..]),
+        Some("ppm") => (Mode::Export(ImageFormat::Ppm), &args[1..]),
+        _ => (Mode::Count, args)
+    };
+    let dimensions = args.first().map(|d| d.parse::<usize>().map_err(|_| format!("not a dimension: {}", d)))
+        .unwrap_or(Ok(4));
+    let rules = args.get(1).map(|r| Rules::parse(r)).unwrap_or_else(|| Ok(Rules::conway()));
+    let cycles = args.get(2).map(|c| c.parse::<usize>().map_err(|_| format!("not a cycle count: {}", c)))
+        .unwrap_or(Ok(6));
+    let file = args.get(3).map(|f| f.as_str()).unwrap_or("day-17-input.txt");
+    let data = match super::lines(file) {
+        Ok(lines) => lines.join("\n"),
+        Err(e) => {
+            println!("could not read {}: {}", file, e);
+            return;
+        }
+    };
+    match (dimensions, rules, cycles) {
+        (Ok(2), Ok(rules), Ok(cycles)) => execute::<2>(&data, rules, cycles, mode),
+        (Ok(3), Ok(rules), Ok(cycles)) => execute::<3>(&data, rules, cycles, mode),
+        (Ok(4), Ok(rules), Ok(cycles)) => execute::<4>(&data, rules, cycles, mode),
+        (Ok(5), Ok(rules), Ok(cycles)) => execute::<5>(&data, rules, cycles, mode),
+        (Ok(d), Ok(_), Ok(_)) => println!("only 2 to 5 dimensions are supported, got {}", d),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => println!("{}", e)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::day17::{Coordinate, Space};
+    use crate::day17::{Coordinate, ImageFormat, Rules, Space};
 
     #[test]
     fn test_neighbors() {
-        let coordinate = Coordinate::origin();
+        let coordinate = Coordinate::<4>::origin();
         let neighbors = coordinate.neighbors();
         assert_eq!(neighbors.len(), 3 * 3 * 3 * 3 - 1);
         assert_eq!(neighbors,
@@ -193,9 +422,81 @@ mod test {
                 .flat_map(move |x| (-1..=1).into_iter()
                     .flat_map(move |y| (-1..=1).into_iter()
                         .flat_map(move |z| (-1..=1).into_iter()
-                            .map(move |w| Coordinate::of(x, y, z, w)))))
+                            .map(move |w| Coordinate([x, y, z, w])))))
                 .filter(|c| *c != coordinate)
-                .collect::<Vec<Coordinate>>())
+                .collect::<Vec<Coordinate<4>>>());
+        assert_eq!(Coordinate::<2>::origin().neighbors().len(), 8);
+        assert_eq!(Coordinate::<3>::origin().neighbors().len(), 26);
+        assert_eq!(Coordinate::<5>::origin().neighbors().len(), 242);
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(Rules::parse("B3/S23"), Ok(Rules::conway()));
+        assert_eq!(Rules::parse("B36/S"), Ok(Rules { birth: vec!(3, 6), survive: vec!() }));
+        assert!(Rules::parse("3/23").is_err());
+        assert!(Rules::parse("B3/Sx").is_err());
+        assert!(Rules::parse("B03/S23").is_err());
+        assert_eq!(Rules::parse("B3,10/S2,3,12"), Ok(Rules { birth: vec!(3, 10), survive: vec!(2, 3, 12) }));
+        assert!(Rules::parse("B3,x/S23").is_err());
+        assert!(Rules::parse("B0,3/S23").is_err());
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let initial_state = ".#.\n..#\n###";
+        assert_eq!(super::simulate::<3>(initial_state, Rules::conway(), 6), 112);
+        // a glider keeps its five cells forever in the plane
+        assert_eq!(super::simulate::<2>(initial_state, Rules::conway(), 6), 5);
+        assert_eq!(super::simulate::<2>(initial_state, Rules::parse("B3/S").unwrap(), 1), 2);
+    }
+
+    #[test]
+    fn test_mirror_symmetry() {
+        assert_eq!(Coordinate([3, -2, -1, 2]).canonical(), Coordinate([3, -2, 1, 2]));
+        assert_eq!(Coordinate([3, -2, 0, 2]).multiplicity(), 2);
+        assert_eq!(Coordinate([3, -2, 1, 2]).multiplicity(), 4);
+        assert_eq!(Coordinate([0, 0, 1, 1]).mirror_images(), vec!(
+            Coordinate([0, 0, 1, 1]), Coordinate([0, 0, 1, -1]), Coordinate([0, 0, -1, 1]), Coordinate([0, 0, -1, -1])));
+        assert_eq!(Coordinate([0, 0, 2, 0]).mirror_images(), vec!(Coordinate([0, 0, 2, 0])));
+    }
+
+    #[test]
+    fn test_cycle_counts() {
+        let initial_state = ".#.\n..#\n###";
+        let counts = |cycles: usize| (
+            super::simulate::<3>(initial_state, Rules::conway(), cycles),
+            super::simulate::<4>(initial_state, Rules::conway(), cycles));
+        assert_eq!(counts(1), (11, 29));
+        assert_eq!(counts(2), (21, 60));
+        assert_eq!(counts(3).0, 38);
+    }
+
+    #[test]
+    fn test_render_worked_example() {
+        let initial_state = ".#.\n..#\n###";
+        let expected = crate::lines("day-17-test.txt").unwrap().join("\n");
+        assert_eq!(super::render::<3>(initial_state, Rules::conway(), 3, false), expected);
+    }
+
+    #[test]
+    fn test_render_non_empty() {
+        let initial_state = ".#.\n..#\n###";
+        let second = super::generations::<4>(initial_state, Rules::conway(), 2).pop().unwrap();
+        let all = second.slices(false);
+        let active = second.slices(true);
+        assert_eq!(all.len(), 25);
+        assert_eq!(active.len(), 8);
+        assert_eq!(active[0], ("z=-2, w=-2".to_string(), ".....\n.....\n..#..\n.....\n.....".to_string()));
+        assert_eq!(active[1].0, "z=0, w=-2");
+        assert!(!active.iter().any(|(label, _)| label == "z=-1, w=-2"));
+        assert_eq!(super::generations::<2>(initial_state, Rules::conway(), 0)[0].render(true), ".#.\n..#\n###");
+    }
+
+    #[test]
+    fn test_images() {
+        assert_eq!(Space::<3>::image(".#\n#.", ImageFormat::Pbm), "P1\n2 2\n0 1\n1 0\n");
+        assert_eq!(Space::<3>::image("#", ImageFormat::Ppm), "P3\n1 1\n255\n255 200 0\n");
     }
 
     #[test]
@@ -203,95 +504,9 @@ mod test {
         let initial_state = ".#.\n\
             ..#\n\
             ###";
-        let start = Space::from(initial_state);
+        let start: Space<4> = Space::from(initial_state);
         let active = (0..6).into_iter().fold(start, |space, _| space.next())
-            .xyz.len();
+            .active();
         assert_eq!(active, 848);
-        // println!("{:?}", start);
-        // assert_eq!(start.string_rep(), vec!(initial_state.to_string()));
-        //
-        // let second = start.next();
-        // let expected_second = vec!(
-        //     "#..\n\
-        //     ..#\n\
-        //     .#.",
-        //     "#.#\n\
-        //     .##\n\
-        //     .#.",
-        //     "#..\n\
-        //     ..#\n\
-        //     .#."
-        // ).into_iter().map(|l| l.to_string()).collect::<Vec<String>>();
-        // assert_eq!(second.string_rep(), expected_second);
-        //
-        // let third = second.next();
-        // let expected_third = vec!(
-        //     ".....\n\
-        //     .....\n\
-        //     ..#..\n\
-        //     .....\n\
-        //     .....",
-        //     "..#..\n\
-        //     .#..#\n\
-        //     ....#\n\
-        //     .#...\n\
-        //     .....",
-        //     "##...\n\
-        //     ##...\n\
-        //     #....\n\
-        //     ....#\n\
-        //     .###.",
-        //     "..#..\n\
-        //     .#..#\n\
-        //     ....#\n\
-        //     .#...\n\
-        //     .....",
-        //     ".....\n\
-        //     .....\n\
-        //     ..#..\n\
-        //     .....\n\
-        //     .....");
-        //
-        // assert_eq!(third.string_rep(), expected_third);
-        //
-        // let fourth = third.next();
-        // let expected_fourth = vec!(
-        //     ".......\n\
-        //     .......\n\
-        //     ..##...\n\
-        //     ..###..\n\
-        //     .......\n\
-        //     .......\n\
-        //     .......",
-        //     "..#....\n\
-        //     ...#...\n\
-        //     #......\n\
-        //     .....##\n\
-        //     .#...#.\n\
-        //     ..#.#..\n\
-        //     ...#...",
-        //     "...#...\n\
-        //     .......\n\
-        //     #......\n\
-        //     .......\n\
-        //     .....##\n\
-        //     .##.#..\n\
-        //     ...#...",
-        //     "..#....\n\
-        //     ...#...\n\
-        //     #......\n\
-        //     .....##\n\
-        //     .#...#.\n\
-        //     ..#.#..\n\
-        //     ...#...",
-        //     ".......\n\
-        //     .......\n\
-        //     ..##...\n\
-        //     ..###..\n\
-        //     .......\n\
-        //     .......\n\
-        //     .......");
-        //
-        // assert_eq!(fourth.string_rep(), expected_fourth);
     }
 }
\ No newline at end of file
@@ -1,10 +1,16 @@
-use std::process::exit;
-use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead};
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 enum Operand {
     Multiply,
-    Add
+    Add,
+    Subtract,
+    Divide,
+    Remainder,
+    Power
 }
 
 impl Operand {
@@ -12,87 +18,213 @@ impl Operand {
         match character {
             '+' => Some(Operand::Add),
             '*' => Some(Operand::Multiply),
+            '-' => Some(Operand::Subtract),
+            '/' => Some(Operand::Divide),
+            '%' => Some(Operand::Remainder),
+            '^' => Some(Operand::Power),
             _ => None
         }
     }
 
-    fn precedence(&self) -> isize {
+    fn symbol(&self) -> char {
         match self {
-            Operand::Multiply => 0,
-            Operand::Add => 1
+            Operand::Multiply => '*',
+            Operand::Add => '+',
+            Operand::Subtract => '-',
+            Operand::Divide => '/',
+            Operand::Remainder => '%',
+            Operand::Power => '^'
         }
     }
-    fn apply(&self, prev: isize, next: isize) -> isize {
+
+    fn apply(&self, prev: isize, next: isize) -> Result<isize, SemanticError> {
+        let overflow = || SemanticError::Overflow(self.clone(), prev, next);
         match self {
-            Operand::Multiply => prev * next,
-            Operand::Add => prev + next
+            Operand::Multiply => prev.checked_mul(next).ok_or_else(overflow),
+            Operand::Add => prev.checked_add(next).ok_or_else(overflow),
+            Operand::Subtract => prev.checked_sub(next).ok_or_else(overflow),
+            Operand::Divide | Operand::Remainder if next == 0 => Err(SemanticError::DivisionByZero(prev)),
+            Operand::Divide => prev.checked_div(next).ok_or_else(overflow),
+            Operand::Remainder => prev.checked_rem(next).ok_or_else(overflow),
+            Operand::Power if next < 0 => Err(SemanticError::NegativeExponent(next)),
+            Operand::Power => u32::try_from(next).ok()
+                .and_then(|exponent| prev.checked_pow(exponent))
+                .ok_or_else(overflow)
+        }
+    }
+
+}
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+enum Associativity {
+    Left,
+    Right
+}
+
+// Binding power and associativity of every operand, higher precedence binds tighter.
+// Unary negation applies to everything binding tighter than its own precedence.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct PrecedenceTable {
+    operands: HashMap<Operand, (isize, Associativity)>,
+    negation: isize
+}
+
+impl PrecedenceTable {
+    fn new(operands: Vec<(Operand, isize, Associativity)>) -> PrecedenceTable {
+        PrecedenceTable {
+            operands: operands.into_iter().map(|(o, p, a)| (o, (p, a))).collect(),
+            negation: isize::MAX
         }
     }
 
+    fn with_negation(self, negation: isize) -> PrecedenceTable {
+        PrecedenceTable { negation, ..self }
+    }
+
+    fn levels(levels: Vec<(Vec<Operand>, Associativity)>) -> PrecedenceTable {
+        PrecedenceTable::new(levels.into_iter().enumerate()
+            .flat_map(|(level, (operands, associativity))| operands.into_iter()
+                .map(move |o| (o, level as isize, associativity)))
+            .collect())
+    }
+
+    fn same_precedence() -> PrecedenceTable {
+        PrecedenceTable::levels(vec!(
+            (vec!(Operand::Add, Operand::Subtract, Operand::Multiply, Operand::Divide, Operand::Remainder, Operand::Power), Associativity::Left)))
+    }
+
+    fn addition_first() -> PrecedenceTable {
+        PrecedenceTable::levels(vec!(
+            (vec!(Operand::Multiply, Operand::Divide, Operand::Remainder), Associativity::Left),
+            (vec!(Operand::Add, Operand::Subtract), Associativity::Left),
+            (vec!(Operand::Power), Associativity::Right)))
+    }
+
+    fn standard_math() -> PrecedenceTable {
+        PrecedenceTable::levels(vec!(
+            (vec!(Operand::Add, Operand::Subtract), Associativity::Left),
+            (vec!(Operand::Multiply, Operand::Divide, Operand::Remainder), Associativity::Left),
+            (vec!(), Associativity::Left),
+            (vec!(Operand::Power), Associativity::Right)))
+            .with_negation(2)
+    }
+
+    // Levels from loosest to tightest binding separated by '<', e.g. "* < +" for addition first.
+    // Operands are left associative unless suffixed with 'r', "neg" places unary negation.
+    fn parse(table: &str) -> Result<PrecedenceTable, String> {
+        let entries = table.split('<').enumerate()
+            .flat_map(|(level, operands)| operands.split_whitespace().map(move |o| (level as isize, o)))
+            .collect::<Vec<(isize, &str)>>();
+        let negation = entries.iter().find(|(_, o)| *o == "neg").map(|(level, _)| *level);
+        entries.into_iter()
+            .filter(|(_, o)| *o != "neg")
+            .map(|(level, operand)| {
+                let (symbol, associativity) = match operand.strip_suffix('r') {
+                    Some(symbol) => (symbol, Associativity::Right),
+                    None => (operand, Associativity::Left)
+                };
+                let mut chars = symbol.chars();
+                match (chars.next().and_then(Operand::from), chars.next()) {
+                    (Some(o), None) => Ok((o, level, associativity)),
+                    _ => Err(format!("unknown operand {} in precedence table {}", operand, table))
+                }
+            })
+            .collect::<Result<Vec<(Operand, isize, Associativity)>, String>>()
+            .map(PrecedenceTable::new)
+            .map(|t| match negation {
+                Some(level) => t.with_negation(level),
+                None => t
+            })
+    }
+
+    fn modes() -> Vec<(&'static str, PrecedenceTable)> {
+        vec!(
+            ("same precedence", PrecedenceTable::same_precedence()),
+            ("addition first", PrecedenceTable::addition_first()),
+            ("standard math", PrecedenceTable::standard_math()))
+    }
+
+    fn get(&self, operand: &Operand) -> Option<(isize, Associativity)> {
+        self.operands.get(operand).copied()
+    }
 }
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 enum Grammar {
     GroupStart,
     GroupEnd,
     Number(isize),
+    Variable(String),
     Operation(Operand)
 }
+// Byte offsets into the source line, end exclusive.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct Span {
+    start: usize,
+    end: usize
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct Token {
+    grammar: Grammar,
+    span: Span
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 enum ParseError {
-    FailedAt(String)
+    FailedAt(String, Span)
 }
 impl Grammar {
     fn parse(expression: &str) -> Result<Vec<Grammar>, ParseError> {
-        if expression.is_empty() {
-            return Ok(vec!());
-        }
-
-        let (g, rem) = Grammar::parse_next(expression);
-
-        if rem == expression {
-           return Err(ParseError::FailedAt(rem.to_string())) ;
-        }
+        Grammar::tokenize(expression).map(|tokens| tokens.into_iter().map(|t| t.grammar).collect())
+    }
 
-        let mut result = g.into_iter().collect::<Vec<Grammar>>();
-        Grammar::parse(rem.as_str())
-            .map(|g| {
-                result.extend(g);
-                result
-            })
+    fn tokenize(expression: &str) -> Result<Vec<Token>, ParseError> {
+        Lexer::new(expression).collect()
     }
-    fn parse_next(expression: &str) -> (Option<Grammar>, String) {
-        let expression = expression.trim();
+}
 
-        if expression.starts_with(')') {
-            let trimmed = expression.strip_prefix(')').unwrap_or(expression);
-           return (Some(Grammar::GroupEnd), trimmed.to_string())
-        } else if expression.starts_with('(') {
-            let trimmed = expression.strip_prefix('(').unwrap_or(expression);
-            return (Some(Grammar::GroupStart), trimmed.to_string())
-        }
+// Walks the source in a single pass, only allocating for variable names.
+struct Lexer<'a> {
+    source: &'a str,
+    position: usize
+}
 
-        expression.chars().into_iter().take_while(|c| c.is_numeric()).collect::<String>().parse::<isize>()
-            .ok()
-            .map(|n| {
-                let after_numbers = expression.chars().into_iter().skip_while(|c| c.is_numeric()).collect::<String>();
-                (Some(Grammar::Number(n)), after_numbers)
-            })
-            .unwrap_or_else(|| {
-                let mut chars = expression.chars();
-                chars.nth(0).and_then(|c| Operand::from(c))
-                    .map(|o| (Some(Grammar::Operation(o)), chars.collect::<String>()))
-                    .unwrap_or((None, expression.to_string()))
-            })
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Lexer<'a> {
+        Lexer { source, position: 0 }
+    }
 
+    fn advance(&self, start: usize, predicate: impl Fn(char) -> bool) -> usize {
+        self.source[start..].char_indices()
+            .find(|(_, c)| !predicate(*c))
+            .map(|(i, _)| start + i)
+            .unwrap_or(self.source.len())
     }
+}
 
-    fn is_grouping(&self) -> bool {
-        match self {
-            Grammar::GroupStart => true,
-            Grammar::GroupEnd => true,
-            _ => false
-        }
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, ParseError>;
 
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.advance(self.position, char::is_whitespace);
+        let first = self.source[start..].chars().next()?;
+        let end = match first {
+            c if c.is_ascii_digit() => self.advance(start, |c| c.is_ascii_digit()),
+            c if c.is_alphabetic() || c == '_' => self.advance(start, |c| c.is_alphanumeric() || c == '_'),
+            c => start + c.len_utf8()
+        };
+        let text = &self.source[start..end];
+        let grammar = match first {
+            '(' => Some(Grammar::GroupStart),
+            ')' => Some(Grammar::GroupEnd),
+            c if c.is_ascii_digit() => text.parse::<isize>().ok().map(Grammar::Number),
+            c if c.is_alphabetic() || c == '_' => Some(Grammar::Variable(text.to_string())),
+            c => Operand::from(c).map(Grammar::Operation)
+        };
+        let span = Span { start, end };
+        self.position = if grammar.is_some() { end } else { self.source.len() };
+        Some(grammar.map(|grammar| Token { grammar, span })
+            .ok_or_else(|| ParseError::FailedAt(self.source[start..].to_string(), span)))
     }
 }
 
@@ -100,12 +232,9 @@ impl Grammar {
 enum Expression {
     Group(Box<Expression>),
     Integer(isize),
-    Operation(Operand, Box<Expression>, Box<Expression>)
-}
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-enum Builder {
-    Expr(Expression),
-    Op(Expression, Operand)
+    Variable(String),
+    Negate(Span, Box<Expression>),
+    Operation(Operand, Span, Box<Expression>, Box<Expression>)
 }
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 enum SemanticError {
@@ -115,181 +244,335 @@ enum SemanticError {
     MisplaceMultiplication(usize),
     ExpectedExpression(Box<Grammar>),
     ExpectedOperand(Box<Grammar>),
+    NoPrecedence(Operand),
+    UnboundVariable(String),
+    Overflow(Operand, isize, isize),
+    DivisionByZero(isize),
+    NegativeExponent(isize),
     BadGrammar(ParseError)
 }
 
-impl Expression {
-    fn evaluate(expr: &str) -> Result<isize, SemanticError> {
-        Grammar::parse(expr).map_err(|e| SemanticError::BadGrammar(e))
-            .and_then(Expression::parse)
-            .map(|e| e.compute())
-    }
-
-    fn compute(&self) -> isize {
+impl Display for SemanticError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Expression::Integer(i) => i.clone(),
-            Expression::Group(e) => e.compute(),
-            Expression::Operation(o, p, n) => o.apply(p.compute(), n.compute())
+            SemanticError::Empty => write!(f, "Expression is empty"),
+            SemanticError::IllegalCharacter(message, _) => write!(f, "{}", message),
+            SemanticError::NoPrecedence(o) => write!(f, "No precedence defined for {:?}", o),
+            SemanticError::UnboundVariable(name) => write!(f, "Variable {} is not bound", name),
+            SemanticError::Overflow(o, prev, next) => write!(f, "{:?} of {} and {} overflows", o, prev, next),
+            SemanticError::DivisionByZero(prev) => write!(f, "Cannot divide {} by zero", prev),
+            SemanticError::NegativeExponent(next) => write!(f, "Exponent {} is negative", next),
+            SemanticError::BadGrammar(ParseError::FailedAt(rem, _)) => match rem.chars().next() {
+                Some(c) => write!(f, "Unexpected character '{}'", c),
+                None => write!(f, "Unexpected end of input")
+            },
+            other => write!(f, "{:?}", other)
         }
     }
+}
 
-    fn parse(grammar: Vec<Grammar>)-> Result<Expression, SemanticError> {
-        let mut iter = grammar.clone().into_iter();
-        let mut idx = 0;
-        let mut next = iter.next();
-        let mut builderStack = vec!();
-        let mut builder: Option<Builder>= None;
-
-        while next.is_some() {
-            match next.clone() {
-                Some(Grammar::GroupStart) => {
-                    builderStack.push(builder.clone());
-                    match builder {
-                        Some(Builder::Expr(e)) => return Err(SemanticError::IllegalCharacter("Group cannot follow expression".to_string(), idx)),
-                        _ => {
-                            builder = None
-                        }
-                    }
-                },
-                Some(Grammar::GroupEnd) => {
-                    let e = match builder.clone() {
-                        Some(Builder::Expr(e)) => Expression::Group(Box::new(e)),
-                        Some(Builder::Op(e, o)) => return Err(SemanticError::IllegalCharacter("Group ended in operation, but should be a complete expression".to_string(), idx)),
-                        None => return Err(SemanticError::IllegalCharacter("Group cannot be empty".to_string(), idx))
-                    };
-
-                    let prev = match builderStack.pop() {
-                        None => return Err(SemanticError::IllegalCharacter("Ended a group when none existed".to_string(), idx)),
-                        Some(Some(Builder::Expr(e))) => return Err(SemanticError::IllegalCharacter("Group cannot immediately follow expression, operand required".to_string(), idx)),
-                        Some(None) => Some(Builder::Expr(e)),
-                        Some(Some(Builder::Op(p, o))) =>
-                            Some(Builder::Expr(p.insert(o, e)))
-                    };
-
-                    builder = prev;
-                }
-                Some(Grammar::Number(i)) => {
-                    match builder {
-                        None => {
-                            builder = Some(Builder::Expr(Expression::Integer(i)));
-                        },
-                        Some(Builder::Expr(e)) => return Err(SemanticError::IllegalCharacter("Expression cannot follow expression, operand required".to_string(), idx)),
-                        Some(Builder::Op(p, o)) => {
-                            builder = Some(Builder::Expr(p.insert(o, Expression::Integer(i))))
-                        }
-                    }
-                },
-                Some(Grammar::Operation(o)) => {
-                    match builder{
-                        None => return Err(SemanticError::IllegalCharacter("Operation requires left expression".to_string(), idx)),
-                        Some(Builder::Op(p, o)) => return Err(SemanticError::IllegalCharacter("Operation cannot follow Operation, expression required".to_string(), idx)),
-                        Some(Builder::Expr(e)) => builder = Some(Builder::Op(e, o))
-                    }
-                }
-                None => {}
+// An error located in its source line, rendered with a caret under the offending span.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Diagnostic {
+    source: String,
+    span: Span,
+    error: SemanticError
+}
+
+impl Diagnostic {
+    fn locate(source: &str, tokens: &[Token], error: SemanticError) -> Diagnostic {
+        let end_of_input = Span { start: source.trim_end().len(), end: source.trim_end().len() + 1 };
+        let span = match &error {
+            SemanticError::BadGrammar(ParseError::FailedAt(_, span)) => *span,
+            SemanticError::IllegalCharacter(_, idx) => tokens.get(*idx as usize).map(|t| t.span).unwrap_or(end_of_input),
+            SemanticError::UnboundVariable(name) => tokens.iter()
+                .find(|t| t.grammar == Grammar::Variable(name.clone()))
+                .map(|t| t.span)
+                .unwrap_or(end_of_input),
+            SemanticError::NoPrecedence(o) => tokens.iter()
+                .find(|t| t.grammar == Grammar::Operation(o.clone()))
+                .map(|t| t.span)
+                .unwrap_or(end_of_input),
+            _ => match (tokens.first(), tokens.last()) {
+                (Some(first), Some(last)) => Span { start: first.span.start, end: last.span.end },
+                _ => end_of_input
             }
-            next = iter.next();
-            idx = idx + 1;
         };
+        Diagnostic { source: source.to_string(), span, error }
+    }
+
+    // Runtime errors come with the span of the failing operator when there is one.
+    fn runtime(source: &str, tokens: &[Token], (error, span): (SemanticError, Option<Span>)) -> Diagnostic {
+        match span {
+            Some(span) => Diagnostic { source: source.to_string(), span, error },
+            None => Diagnostic::locate(source, tokens, error)
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let column = self.source.get(..self.span.start).map(|s| s.chars().count()).unwrap_or(self.span.start);
+        let width = self.source.get(self.span.start..self.span.end).map(|s| s.chars().count()).unwrap_or(1).max(1);
+        writeln!(f, "{} at column {}", self.error, column + 1)?;
+        writeln!(f, "{}", self.source)?;
+        write!(f, "{}{}", " ".repeat(column), "^".repeat(width))
+    }
+}
+
+impl Expression {
+    fn evaluate(expr: &str, table: &PrecedenceTable) -> Result<isize, SemanticError> {
+        Expression::evaluate_with(expr, table, &HashMap::new())
+    }
+
+    fn evaluate_with(expr: &str, table: &PrecedenceTable, environment: &HashMap<String, isize>) -> Result<isize, SemanticError> {
+        Grammar::tokenize(expr).map_err(SemanticError::BadGrammar)
+            .and_then(|tokens| Expression::parse(tokens, table))
+            .and_then(|e| e.compute_located(environment).map_err(|(e, _)| e))
+    }
+
+    fn evaluate_located(expr: &str, table: &PrecedenceTable, environment: &HashMap<String, isize>) -> Result<isize, Diagnostic> {
+        Expression::parse_located(expr, table)
+            .and_then(|(e, tokens)| e.compute_located(environment).map_err(|e| Diagnostic::runtime(expr, &tokens, e)))
+    }
+
+    fn parse_located(expr: &str, table: &PrecedenceTable) -> Result<(Expression, Vec<Token>), Diagnostic> {
+        let tokens = Grammar::tokenize(expr)
+            .map_err(|e| Diagnostic::locate(expr, &[], SemanticError::BadGrammar(e)))?;
+        Expression::parse(tokens.clone(), table)
+            .map(|e| (e, tokens.clone()))
+            .map_err(|e| Diagnostic::locate(expr, &tokens, e))
+    }
 
-        return match builder {
-            Some(Builder::Expr(e)) => Ok(e),
-            Some(Builder::Op(e, o)) => Err(SemanticError::IllegalCharacter("Expression cannot end in an operand".to_string(), idx)),
-            None => Err(SemanticError::Empty)
+    // Every operation wrapped in its own parentheses, showing how the table associated it.
+    fn parenthesized(&self) -> String {
+        match self {
+            Expression::Integer(i) => i.to_string(),
+            Expression::Variable(name) => name.clone(),
+            Expression::Group(e) => e.parenthesized(),
+            Expression::Negate(_, e) => format!("(-{})", e.parenthesized()),
+            Expression::Operation(o, _, p, n) => format!("({} {} {})", p.parenthesized(), o.symbol(), n.parenthesized())
         }
     }
 
-    fn insert(&self, op: Operand, next: Expression) -> Expression {
+    fn tree(&self) -> Vec<String> {
+        let (node, children) = match self {
+            Expression::Integer(i) => (i.to_string(), vec!()),
+            Expression::Variable(name) => (name.clone(), vec!()),
+            Expression::Group(e) => ("()".to_string(), vec!(e.as_ref())),
+            Expression::Negate(_, e) => ("neg".to_string(), vec!(e.as_ref())),
+            Expression::Operation(o, _, p, n) => (o.symbol().to_string(), vec!(p.as_ref(), n.as_ref()))
+        };
+        let mut lines = vec!(node);
+        lines.extend(children.into_iter()
+            .flat_map(|c| c.tree())
+            .map(|l| format!("  {}", l)));
+        lines
+    }
+
+    // Failing operations report their operator's span, unbound variables are located by name.
+    fn compute_located(&self, environment: &HashMap<String, isize>) -> Result<isize, (SemanticError, Option<Span>)> {
         match self {
-            Expression::Integer(i) => Expression::Operation(op, Box::new(Expression::Integer(i.clone())), Box::new(next)),
-            Expression::Group(e) => Expression::Operation(op, Box::new(Expression::Group(e.clone())), Box::new(next)),
-            Expression::Operation(prev_op, p, e) => {
-                if(prev_op.precedence() > op.precedence()) {
-                    Expression::Operation(op, Box::new(Expression::Operation(prev_op.clone(), p.clone(), e.clone())), Box::new(next))
-                } else {
-                    Expression::Operation(prev_op.clone(), p.clone(), Box::new(e.insert(op, next)))
+            Expression::Integer(i) => Ok(*i),
+            Expression::Variable(name) => environment.get(name).copied()
+                .ok_or_else(|| (SemanticError::UnboundVariable(name.clone()), None)),
+            Expression::Group(e) => e.compute_located(environment),
+            Expression::Negate(span, e) => e.compute_located(environment)
+                .and_then(|value| Operand::Subtract.apply(0, value).map_err(|e| (e, Some(*span)))),
+            Expression::Operation(_, _, _, _) => {
+                // Walk the left spine in a loop so long left associated chains don't recurse per operand.
+                let mut spine = vec!();
+                let mut left = self;
+                while let Expression::Operation(o, span, p, n) = left {
+                    spine.push((o, span, n));
+                    left = p;
                 }
+                spine.into_iter().rev()
+                    .try_fold(left.compute_located(environment)?, |value, (o, span, n)| o
+                        .apply(value, n.compute_located(environment)?)
+                        .map_err(|e| (e, Some(*span))))
             }
         }
     }
+
+    fn parse(tokens: Vec<Token>, table: &PrecedenceTable) -> Result<Expression, SemanticError> {
+        if tokens.is_empty() {
+            return Err(SemanticError::Empty);
+        }
+        let mut parser = ExpressionParser { tokens, idx: 0, depth: 0, table };
+        let expression = parser.parse_expression(isize::MIN)?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(Grammar::GroupEnd) => Err(parser.error("Ended a group when none existed")),
+            Some(Grammar::GroupStart) => Err(parser.error("Group cannot follow expression")),
+            Some(_) => Err(parser.error("Expression cannot follow expression, operand required"))
+        }
+    }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-struct Computation {
-    soFar: Option<isize>,
-    rem: Vec<Grammar>
+// Parsing, computing and printing recurse once per nesting level (groups, negations and right
+// associated operands), so deeper expressions are rejected instead of overflowing the stack.
+const MAX_NESTING: usize = 500;
+
+// Precedence climbing over the grammar: an operand only joins the expression on its left
+// while it binds at least as tightly as the operand that started that expression.
+struct ExpressionParser<'a> {
+    tokens: Vec<Token>,
+    idx: usize,
+    depth: usize,
+    table: &'a PrecedenceTable
 }
 
-impl Computation {
-    fn from(expression: &str) -> Option<Computation> {
-        Grammar::parse(expression).ok()
-            .map(|g| {
-                Computation { soFar: None, rem: g }
-            })
+impl<'a> ExpressionParser<'a> {
+    fn peek(&self) -> Option<&Grammar> {
+        self.tokens.get(self.idx).map(|t| &t.grammar)
     }
 
-    fn evaluate_next(&self) -> Option<Computation> {
-        let mut parts = self.rem.clone().into_iter();
-        // let mut evaluatable = parts.take_while(|p| !p.is_grouping()).collect::<Vec<Grammar>>();
-        match parts.next() {
-            Some(Grammar::Number(i)) => Some(Computation { soFar: Some(i), rem: parts.collect()}),
-            Some(Grammar::Operation(o)) => {
-                self.soFar.and_then(|prev|
-                   Computation { soFar: None, rem: parts.collect() }
-                       .evaluate_next()
-                       .map(|c|
-                           Computation {
-                               soFar: c.soFar.map(|next| o.apply(prev, next) ),
-                               rem: c.rem
-                           }))
-            },
-            Some(Grammar::GroupStart) =>
-                Computation { soFar: None, rem: parts.collect() }.evaluate_expression(),
-            Some(Grammar::GroupEnd) => {
-                None
-            },
-            None => None
-        }
+    // Span of the token at idx, only called after peek found one.
+    fn span(&self) -> Span {
+        self.tokens[self.idx].span
     }
 
-    fn evaluate_expression(&self) -> Option<Computation> {
-        let next = self.evaluate_next();
-        match next {
-            Some(computation) => computation.evaluate_expression(),
-            None =>{
-                let rem = self.rem.first().and_then(|c| match c {
-                    Grammar::GroupEnd => {
-                        let mut rem = self.rem.clone();
-                        rem.remove(0);
-                        Some(rem)
-                    },
-                    _ => None
-                }).unwrap_or(self.rem.clone());
-                Some(Computation { soFar: self.soFar, rem: rem })
-            }
+    fn error(&self, message: &str) -> SemanticError {
+        SemanticError::IllegalCharacter(message.to_string(), self.idx as isize)
+    }
 
+    fn parse_expression(&mut self, min_precedence: isize) -> Result<Expression, SemanticError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error(format!("Expression is nested more than {} levels deep", MAX_NESTING).as_str()));
+        }
+        self.depth += 1;
+        let expression = self.parse_operations(min_precedence);
+        self.depth -= 1;
+        expression
+    }
 
+    fn parse_operations(&mut self, min_precedence: isize) -> Result<Expression, SemanticError> {
+        let mut expression = self.parse_primary()?;
+        while let Some(Grammar::Operation(o)) = self.peek().cloned() {
+            let (precedence, associativity) = self.table.get(&o).ok_or(SemanticError::NoPrecedence(o.clone()))?;
+            if precedence < min_precedence {
+                break;
+            }
+            let span = self.span();
+            self.idx += 1;
+            let next_min = match associativity {
+                Associativity::Left => precedence + 1,
+                Associativity::Right => precedence
+            };
+            let right = self.parse_expression(next_min)?;
+            expression = Expression::Operation(o, span, Box::new(expression), Box::new(right));
         }
+        Ok(expression)
     }
 
+    fn parse_primary(&mut self) -> Result<Expression, SemanticError> {
+        let previous = if self.idx == 0 { None } else { self.tokens.get(self.idx - 1).map(|t| t.grammar.clone()) };
+        match (self.peek().cloned(), previous) {
+            (Some(Grammar::Number(i)), _) => {
+                self.idx += 1;
+                Ok(Expression::Integer(i))
+            },
+            (Some(Grammar::Variable(name)), _) => {
+                self.idx += 1;
+                Ok(Expression::Variable(name))
+            },
+            (Some(Grammar::Operation(Operand::Subtract)), _) => {
+                let span = self.span();
+                self.idx += 1;
+                let operand = self.parse_expression(self.table.negation)?;
+                Ok(Expression::Negate(span, Box::new(operand)))
+            },
+            (Some(Grammar::GroupStart), _) => {
+                self.idx += 1;
+                let expression = self.parse_expression(isize::MIN)?;
+                match self.peek() {
+                    Some(Grammar::GroupEnd) => {
+                        self.idx += 1;
+                        Ok(Expression::Group(Box::new(expression)))
+                    },
+                    None => Err(self.error("Group was never closed")),
+                    Some(Grammar::GroupStart) => Err(self.error("Group cannot follow expression")),
+                    Some(_) => Err(self.error("Expression cannot follow expression, operand required"))
+                }
+            },
+            (Some(Grammar::GroupEnd), Some(Grammar::GroupStart)) => Err(self.error("Group cannot be empty")),
+            (Some(Grammar::GroupEnd), _) => Err(self.error("Group ended in operation, but should be a complete expression")),
+            (Some(Grammar::Operation(_)), Some(Grammar::Operation(_))) => Err(self.error("Operation cannot follow Operation, expression required")),
+            (Some(Grammar::Operation(_)), _) => Err(self.error("Operation requires left expression")),
+            (None, _) => Err(self.error("Expression cannot end in an operand"))
+        }
+    }
 }
 
 pub fn run() {
-    let sum: isize = super::lines("day-18-input.txt").unwrap().into_iter()
-        .flat_map(|l| Expression::evaluate(l.as_str()).ok())
-        .sum();
-    println!("sum: {}", sum)
+    let lines = super::lines("day-18-input.txt").unwrap();
+    PrecedenceTable::modes().into_iter().for_each(|(name, table)| {
+        let (values, failures): (Vec<_>, Vec<_>) = lines.iter()
+            .map(|l| Expression::evaluate_located(l.as_str(), &table, &HashMap::new()))
+            .enumerate()
+            .partition(|(_, r)| r.is_ok());
+        let sum: isize = values.into_iter().flat_map(|(_, r)| r.ok()).sum();
+        println!("sum with {}: {}", name, sum);
+        failures.into_iter()
+            .flat_map(|(line, r)| r.err().map(|d| (line, d)))
+            .for_each(|(line, diagnostic)| println!("line {}: {}", line + 1, diagnostic))
+    });
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Dump {
+    tree: bool,
+    parens: bool
+}
+
+// Result of a single line under every precedence mode, optionally followed by its parse.
+fn eval_line(line: &str, dump: Dump) -> Vec<String> {
+    PrecedenceTable::modes().into_iter()
+        .flat_map(|(name, table)| match Expression::parse_located(line, &table) {
+            Err(diagnostic) => vec!(format!("{}: {}", name, diagnostic)),
+            Ok((expression, tokens)) => {
+                let value = expression.compute_located(&HashMap::new())
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|e| Diagnostic::runtime(line, &tokens, e).to_string());
+                let mut output = vec!(format!("{}: {}", name, value));
+                if dump.parens {
+                    output.push(format!("  {}", expression.parenthesized()));
+                }
+                if dump.tree {
+                    output.extend(expression.tree().into_iter().map(|l| format!("  {}", l)));
+                }
+                output
+            }
+        })
+        .collect()
+}
+
+fn eval(dump: Dump) {
+    io::stdin().lock().lines()
+        .map_while(|l| l.ok())
+        .filter(|l| !l.trim().is_empty())
+        .for_each(|l| eval_line(l.as_str(), dump).into_iter().for_each(|o| println!("{}", o)));
+}
+
+pub fn run_args(args: &[String]) {
+    match args.first().map(|a| a.as_str()) {
+        Some("eval") => eval(Dump {
+            tree: args.iter().any(|a| a == "tree"),
+            parens: args.iter().any(|a| a == "parens")
+        }),
+        _ => run()
+    }
 }
 
 fn evaluate(expression: &str) -> Option<isize> {
-   Computation::from(expression)
-       .and_then(|c| c.evaluate_expression())
-       .and_then(|c| c.soFar)
+    Expression::evaluate(expression, &PrecedenceTable::same_precedence()).ok()
 }
 
 #[cfg(test)]
 mod test {
-    use crate::day18::{evaluate, Grammar, Expression, Operand};
+    use crate::day18::{eval_line, evaluate, Associativity, MAX_NESTING, Dump, Grammar, Expression, Operand, PrecedenceTable, SemanticError, Span, Token};
+    use std::collections::HashMap;
 
     fn assert_evaluation(expression: &str, expected: isize) {
         assert_eq!(evaluate(expression), Some(expected))
@@ -326,15 +609,17 @@ mod test {
     #[test]
     fn test_idx() {
         let expression = "1 + 2 * 3";
-        let expr = Grammar::parse(expression)
-            .map(Expression::parse);
+        let expr = Grammar::tokenize(expression)
+            .map(|t| Expression::parse(t, &PrecedenceTable::addition_first()));
 
         assert_eq!(
             expr.unwrap().unwrap(),
             Expression::Operation(
                 Operand::Multiply,
+                Span { start: 6, end: 7 },
                 Box::new(Expression::Operation(
                     Operand::Add,
+                    Span { start: 2, end: 3 },
                     Box::new(Expression::Integer(1)),
                     Box::new(Expression::Integer(2)),
                 )),
@@ -343,7 +628,7 @@ mod test {
 
     fn test_compute(expr: &str, expected: isize) {
         assert_eq!(
-            Expression::evaluate(expr),
+            Expression::evaluate(expr, &PrecedenceTable::addition_first()),
             Ok(expected)
         )
     }
@@ -357,4 +642,180 @@ mod test {
 
     }
 
+    #[test]
+    fn test_precedence_tables() {
+        let expression = "2 * 3 + (4 * 5) + 1 * 2";
+        assert_eq!(Expression::evaluate(expression, &PrecedenceTable::same_precedence()), Ok(54));
+        assert_eq!(Expression::evaluate(expression, &PrecedenceTable::addition_first()), Ok(96));
+        assert_eq!(Expression::evaluate(expression, &PrecedenceTable::standard_math()), Ok(28));
+        assert_eq!(PrecedenceTable::parse("* / % < + - < ^r"), Ok(PrecedenceTable::addition_first()));
+        assert_eq!(PrecedenceTable::parse("+ - < * / % < neg < ^r"), Ok(PrecedenceTable::standard_math()));
+        assert_eq!(PrecedenceTable::parse("+ - * / % ^"), Ok(PrecedenceTable::same_precedence()));
+        assert!(PrecedenceTable::parse("+ < x").is_err());
+    }
+
+    #[test]
+    fn test_associativity() {
+        let right = PrecedenceTable::new(vec!(
+            (Operand::Add, 0, Associativity::Right),
+            (Operand::Multiply, 0, Associativity::Right)));
+        assert_eq!(PrecedenceTable::parse("+r *r"), Ok(right.clone()));
+        assert_eq!(Expression::evaluate("2 ^ 3 ^ 2", &PrecedenceTable::standard_math()), Ok(512));
+        assert_eq!(Expression::evaluate("2 * 3 + 4", &right), Ok(14));
+        assert_eq!(
+            Grammar::tokenize("1 + 2 + 3").map(|t| Expression::parse(t, &right)).unwrap(),
+            Ok(Expression::Operation(
+                Operand::Add,
+                Span { start: 2, end: 3 },
+                Box::new(Expression::Integer(1)),
+                Box::new(Expression::Operation(
+                    Operand::Add,
+                    Span { start: 6, end: 7 },
+                    Box::new(Expression::Integer(2)),
+                    Box::new(Expression::Integer(3)))))));
+        let missing = PrecedenceTable::parse("+").unwrap();
+        assert_eq!(Expression::evaluate("1 * 2", &missing), Err(SemanticError::NoPrecedence(Operand::Multiply)));
+    }
+
+    #[test]
+    fn test_semantic_errors() {
+        let table = PrecedenceTable::addition_first();
+        let error = |expr: &str| Expression::evaluate(expr, &table);
+        assert_eq!(error(""), Err(SemanticError::Empty));
+        assert_eq!(error("1 +"), Err(SemanticError::IllegalCharacter("Expression cannot end in an operand".to_string(), 2)));
+        assert_eq!(error("+ 1"), Err(SemanticError::IllegalCharacter("Operation requires left expression".to_string(), 0)));
+        assert_eq!(error("1 + * 2"), Err(SemanticError::IllegalCharacter("Operation cannot follow Operation, expression required".to_string(), 2)));
+        assert_eq!(error("(1 + 2"), Err(SemanticError::IllegalCharacter("Group was never closed".to_string(), 4)));
+        assert_eq!(error("1 + 2)"), Err(SemanticError::IllegalCharacter("Ended a group when none existed".to_string(), 3)));
+        assert_eq!(error("1 ()"), Err(SemanticError::IllegalCharacter("Group cannot follow expression".to_string(), 1)));
+        assert_eq!(error("2 + ()"), Err(SemanticError::IllegalCharacter("Group cannot be empty".to_string(), 3)));
+        assert_eq!(error("(2 +)"), Err(SemanticError::IllegalCharacter("Group ended in operation, but should be a complete expression".to_string(), 3)));
+    }
+
+    #[test]
+    fn test_extended_operands() {
+        let table = PrecedenceTable::standard_math();
+        assert_eq!(Expression::evaluate("10 - 4 - 3", &table), Ok(3));
+        assert_eq!(Expression::evaluate("20 / 3 % 4", &table), Ok(2));
+        assert_eq!(Expression::evaluate("1 + 2 * 3 ^ 2", &table), Ok(19));
+        assert_eq!(Expression::evaluate("-7 / 2", &table), Ok(-3));
+        assert_eq!(Expression::evaluate("(1 + 2) * 3 - 4", &PrecedenceTable::same_precedence()), Ok(5));
+        assert_eq!(Expression::evaluate("2 * 3 - 1", &PrecedenceTable::addition_first()), Ok(4));
+    }
+
+    #[test]
+    fn test_negation() {
+        let table = PrecedenceTable::standard_math();
+        assert_eq!(Expression::evaluate("-2 ^ 2", &table), Ok(-4));
+        assert_eq!(Expression::evaluate("-2 * 3", &table), Ok(-6));
+        assert_eq!(Expression::evaluate("1 - -2", &table), Ok(3));
+        assert_eq!(Expression::evaluate("--(2 + 3)", &table), Ok(5));
+        assert_eq!(Expression::evaluate("-2 ^ 2", &PrecedenceTable::addition_first()), Ok(4));
+    }
+
+    #[test]
+    fn test_variables() {
+        let table = PrecedenceTable::standard_math();
+        let environment: HashMap<String, isize> = vec!(("x".to_string(), 3), ("rate_2".to_string(), 10)).into_iter().collect();
+        assert_eq!(Expression::evaluate_with("x * rate_2 + x", &table, &environment), Ok(33));
+        assert_eq!(Expression::evaluate_with("-x^2", &table, &environment), Ok(-9));
+        assert_eq!(Expression::evaluate_with("x * y", &table, &environment), Err(SemanticError::UnboundVariable("y".to_string())));
+        assert_eq!(Expression::evaluate_with("x 2", &table, &environment),
+                   Err(SemanticError::IllegalCharacter("Expression cannot follow expression, operand required".to_string(), 1)));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let table = PrecedenceTable::standard_math();
+        assert_eq!(Expression::evaluate("1 / (2 - 2)", &table), Err(SemanticError::DivisionByZero(1)));
+        assert_eq!(Expression::evaluate("1 % 0", &table), Err(SemanticError::DivisionByZero(1)));
+        assert_eq!(Expression::evaluate("2 ^ -1", &table), Err(SemanticError::NegativeExponent(-1)));
+        assert_eq!(Expression::evaluate("2 ^ 64", &table), Err(SemanticError::Overflow(Operand::Power, 2, 64)));
+        assert_eq!(Expression::evaluate("9223372036854775807 + 1", &table),
+                   Err(SemanticError::Overflow(Operand::Add, isize::MAX, 1)));
+        assert_eq!(Expression::evaluate("-9223372036854775807 - 2", &table),
+                   Err(SemanticError::Overflow(Operand::Subtract, -isize::MAX, 2)));
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let spans = |expr: &str| Grammar::tokenize(expr).map(|tokens| tokens.into_iter()
+            .map(|Token { span, .. }| (span.start, span.end))
+            .collect::<Vec<(usize, usize)>>());
+        assert_eq!(spans(" 12 +(rate*3) "), Ok(vec!((1, 3), (4, 5), (5, 6), (6, 10), (10, 11), (11, 12), (12, 13))));
+        assert_eq!(Grammar::tokenize("1 + $2").map(|_| ()).unwrap_err(),
+                   crate::day18::ParseError::FailedAt("$2".to_string(), Span { start: 4, end: 5 }));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let table = PrecedenceTable::standard_math();
+        let diagnose = |expr: &str| Expression::evaluate_located(expr, &table, &HashMap::new())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(diagnose("1 + * 2"), "Operation cannot follow Operation, expression required at column 5\n1 + * 2\n    ^");
+        assert_eq!(diagnose("(12 + 3"), "Group was never closed at column 8\n(12 + 3\n       ^");
+        assert_eq!(diagnose("1 + $2"), "Unexpected character '$' at column 5\n1 + $2\n    ^");
+        assert_eq!(diagnose("2 * count"), "Variable count is not bound at column 5\n2 * count\n    ^^^^^");
+        assert_eq!(diagnose("(4 / (2 - 2))"), "Cannot divide 4 by zero at column 4\n(4 / (2 - 2))\n   ^");
+        assert_eq!(diagnose("1 + 2 % 0 * 3"), "Cannot divide 2 by zero at column 7\n1 + 2 % 0 * 3\n      ^");
+        assert_eq!(Expression::evaluate_located("2 ^ 2", &table, &HashMap::new()), Ok(4));
+        let environment = vec!(("x".to_string(), isize::MIN)).into_iter().collect::<HashMap<String, isize>>();
+        assert_eq!(Expression::evaluate_located("1 + -x", &table, &environment).unwrap_err().to_string(),
+                   format!("Subtract of 0 and {} overflows at column 5\n1 + -x\n    ^", isize::MIN));
+    }
+
+    #[test]
+    fn test_printers() {
+        let (expression, _) = Expression::parse_located("1 + 2 * -(3 - x)", &PrecedenceTable::standard_math()).unwrap();
+        assert_eq!(expression.parenthesized(), "(1 + (2 * (-(3 - x))))");
+        assert_eq!(expression.tree(), vec!("+", "  1", "  *", "    2", "    neg", "      ()", "        -", "          3", "          x"));
+    }
+
+    #[test]
+    fn test_eval_line() {
+        assert_eq!(eval_line("1 + 2 * 3", Dump { tree: false, parens: true }), vec!(
+            "same precedence: 9", "  ((1 + 2) * 3)",
+            "addition first: 9", "  ((1 + 2) * 3)",
+            "standard math: 7", "  (1 + (2 * 3))"));
+        assert_eq!(eval_line("2 ^ 3", Dump { tree: true, parens: false })[0..4].to_vec(), vec!(
+            "same precedence: 8", "  ^", "    2", "    3"));
+        assert_eq!(eval_line("1 / 0", Dump { tree: false, parens: false })[0],
+                   "same precedence: Cannot divide 1 by zero at column 3\n1 / 0\n  ^");
+    }
+
+    #[test]
+    fn test_deep_negation() {
+        let table = PrecedenceTable::standard_math();
+        let negated = |count: usize| format!("{}1", "-".repeat(count));
+        assert_eq!(Expression::evaluate(negated(MAX_NESTING - 1).as_str(), &table), Ok(-1));
+        let diagnostic = Expression::evaluate_located(negated(3000).as_str(), &table, &HashMap::new()).unwrap_err();
+        assert_eq!(diagnostic.to_string().lines().next(),
+                   Some(format!("Expression is nested more than {} levels deep at column {}", MAX_NESTING, MAX_NESTING + 1).as_str()));
+        let powers = vec!("1"; 3000).join("^");
+        assert!(Expression::evaluate(powers.as_str(), &table).is_err());
+    }
+
+    #[test]
+    fn test_long_expressions() {
+        let terms = 5000;
+        let expression = vec!("1"; terms).join("+");
+        assert_eq!(Grammar::tokenize(expression.as_str()).map(|t| t.len()), Ok(2 * terms - 1));
+        assert_eq!(Expression::evaluate(expression.as_str(), &PrecedenceTable::standard_math()), Ok(terms as isize));
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(Expression::evaluate(nested(MAX_NESTING - 1).as_str(), &PrecedenceTable::standard_math()), Ok(1));
+        let too_deep = nested(3000);
+        assert_eq!(Grammar::tokenize(too_deep.as_str()).map(|t| t.len()), Ok(6001));
+        assert_eq!(Expression::evaluate_located(too_deep.as_str(), &PrecedenceTable::standard_math(), &HashMap::new())
+                       .map_err(|d| d.to_string().lines().next().map(|l| l.to_string())),
+                   Err(Some(format!("Expression is nested more than {} levels deep at column {}", MAX_NESTING, MAX_NESTING + 1))));
+        assert_eq!(Expression::evaluate("12*(34+5)-x2", &PrecedenceTable::standard_math()),
+                   Err(SemanticError::UnboundVariable("x2".to_string())));
+        assert_eq!(Grammar::parse("12*(34+5)"), Ok(vec!(
+            Grammar::Number(12), Grammar::Operation(Operand::Multiply), Grammar::GroupStart,
+            Grammar::Number(34), Grammar::Operation(Operand::Add), Grammar::Number(5), Grammar::GroupEnd)));
+        assert_eq!(Grammar::tokenize("99999999999999999999 + 1").map(|_| ()).unwrap_err(),
+                   crate::day18::ParseError::FailedAt("99999999999999999999 + 1".to_string(), Span { start: 0, end: 20 }));
+    }
+
 }
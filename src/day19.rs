@@ -1,102 +1,855 @@
 use crate::day19::Rule::Sequence;
-use std::collections::HashMap;
-use std::borrow::Borrow;
-use std::ops::Deref;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use regex::Regex;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 enum Rule {
-    Literal(char),
-    Or(Box<Rule>, Box<Rule>),
+    Literal(String),
+    Class(Vec<(char, char)>),
+    Or(Vec<Rule>),
     Sequence(Vec<usize>)
 }
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 enum RuleError {
- DoesNotMatch(char),
+    DoesNotMatch(String),
     NoSuchRule(usize),
-    NoData(usize),
-    HasMore(usize),
+    Undefined { rule: usize, reference: usize },
+    Duplicate(usize),
+    Rejected(usize, Mismatch),
+    Recursive(usize),
+    LeftRecursive(usize),
+    UnitCycle(usize),
+    InvalidRule(String),
+    Compile(String),
+    // The matcher rejected the message at this index but the explainer found a match
+    Disagreement(usize),
     Aggregate(Vec<RuleError>)
 }
 impl Rule {
-    fn parse(rule: &str) -> Option<Rule> {
-        let rule = rule.trim();
-        if rule.starts_with('"') && rule.ends_with('"') && rule.len() == 3 {
-            rule.chars().nth(1).map(|c| Rule::Literal(c.clone()))
-        } else if rule.contains('|') {
-            let mut parts = rule.split('|');
-            let head = parts.next().and_then(Rule::parse);
-            let tail = parts.collect::<Vec<&str>>().join("|");
-            let tail = Rule::parse(tail.as_str());
-
-            match (head, tail) {
-                (Some(r1), Some(r2)) => Some(Rule::Or(Box::new(r1), Box::new(r2))),
-                (Some(r1), None) => Some(r1),
-                _ => None
+    // Alternatives separated by '|', each either a sequence of rule numbers, a quoted
+    // literal such as "ab" or a character class such as [a-z_].
+    fn parse(rule: &str) -> Result<Rule, RuleError> {
+        let invalid = || RuleError::InvalidRule(rule.trim().to_string());
+        let mut alternatives = vec!();
+        let mut current = vec!();
+        let mut chars = rule.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                c if c.is_whitespace() => {},
+                '|' => alternatives.push(Rule::alternative(std::mem::take(&mut current)).ok_or_else(invalid)?),
+                '"' => {
+                    let mut literal = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => literal.push(chars.next().ok_or_else(invalid)?),
+                            Some(c) => literal.push(c),
+                            None => return Err(invalid())
+                        }
+                    }
+                    if literal.is_empty() {
+                        return Err(invalid());
+                    }
+                    current.push(Rule::Literal(literal));
+                },
+                '[' => {
+                    let mut ranges = vec!();
+                    loop {
+                        let from = match chars.next() {
+                            Some(']') => break,
+                            Some('\\') => chars.next().ok_or_else(invalid)?,
+                            Some(c) => c,
+                            None => return Err(invalid())
+                        };
+                        let to = if chars.peek() == Some(&'-') {
+                            chars.next();
+                            match chars.next() {
+                                Some(']') | None => return Err(invalid()),
+                                Some('\\') => chars.next().ok_or_else(invalid)?,
+                                Some(c) => c
+                            }
+                        } else {
+                            from
+                        };
+                        if to < from {
+                            return Err(invalid());
+                        }
+                        ranges.push((from, to));
+                    }
+                    if ranges.is_empty() {
+                        return Err(invalid());
+                    }
+                    current.push(Rule::Class(ranges));
+                },
+                c if c.is_ascii_digit() => {
+                    let mut number = c.to_string();
+                    while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                        number.push(*d);
+                        chars.next();
+                    }
+                    current.push(Rule::Sequence(vec!(number.parse::<usize>().map_err(|_| invalid())?)));
+                },
+                _ => return Err(invalid())
             }
+        }
+        alternatives.push(Rule::alternative(current).ok_or_else(invalid)?);
+        if alternatives.len() == 1 {
+            Ok(alternatives.remove(0))
+        } else {
+            Ok(Rule::Or(alternatives))
+        }
+    }
+
+    // A single literal or class, or a sequence made only of rule numbers.
+    fn alternative(mut parts: Vec<Rule>) -> Option<Rule> {
+        match parts.as_slice() {
+            [] => None,
+            [Rule::Literal(_)] | [Rule::Class(_)] => parts.pop(),
+            _ => parts.into_iter()
+                .map(|p| match p {
+                    Rule::Sequence(rules) => Some(rules),
+                    _ => None
+                })
+                .collect::<Option<Vec<Vec<usize>>>>()
+                .map(|rules| Rule::Sequence(rules.concat()))
+        }
+    }
+
+    fn references(&self) -> Vec<usize> {
+        match self {
+            Rule::Literal(_) | Rule::Class(_) => vec!(),
+            Rule::Or(rules) => rules.iter().flat_map(|r| r.references()).collect(),
+            Rule::Sequence(rules) => rules.clone()
+        }
+    }
+
+    fn alternatives(&self) -> Vec<Vec<Symbol>> {
+        match self {
+            Rule::Literal(literal) => vec!(literal.chars().map(Symbol::Terminal).collect()),
+            Rule::Class(ranges) => vec!(vec!(Symbol::Class(ranges.clone()))),
+            Rule::Or(rules) => rules.iter().flat_map(|r| r.alternatives()).collect(),
+            Rule::Sequence(rules) => vec!(rules.iter().map(|r| Symbol::Rule(*r)).collect())
+        }
+    }
+
+    // Rules that match exactly the same span as this one.
+    fn units(&self) -> Vec<usize> {
+        match self {
+            Rule::Literal(_) | Rule::Class(_) => vec!(),
+            Rule::Or(rules) => rules.iter().flat_map(|r| r.units()).collect(),
+            Rule::Sequence(rules) if rules.len() == 1 => rules.clone(),
+            Rule::Sequence(_) => vec!()
+        }
+    }
+
+    // Derivation depth of the shallowest alternative, given the depths known for referenced rules.
+    fn depth(&self, depths: &HashMap<usize, usize>) -> Option<usize> {
+        match self {
+            Rule::Literal(_) | Rule::Class(_) => Some(0),
+            Rule::Or(rules) => rules.iter().flat_map(|r| r.depth(depths)).min(),
+            Rule::Sequence(rules) => rules.iter()
+                .map(|r| depths.get(r).map(|d| d + 1))
+                .collect::<Option<Vec<usize>>>()
+                .map(|depths| depths.into_iter().max().unwrap_or(0))
+        }
+    }
+
+    // Characters the rule can consume directly, literal characters as single character ranges.
+    fn ranges(&self) -> Vec<(char, char)> {
+        match self {
+            Rule::Literal(literal) => literal.chars().map(|c| (c, c)).collect(),
+            Rule::Class(ranges) => ranges.clone(),
+            Rule::Or(rules) => rules.iter().flat_map(|r| r.ranges()).collect(),
+            Rule::Sequence(_) => vec!()
+        }
+    }
+
+    // Rules that can be entered before any character is consumed.
+    fn leading(&self) -> Vec<usize> {
+        match self {
+            Rule::Literal(_) | Rule::Class(_) => vec!(),
+            Rule::Or(rules) => rules.iter().flat_map(|r| r.leading()).collect(),
+            Rule::Sequence(rules) => rules.first().into_iter().copied().collect()
+        }
+    }
+}
+
+fn in_class(ranges: &[(char, char)], c: char) -> bool {
+    ranges.iter().any(|(from, to)| *from <= c && c <= *to)
+}
+
+fn describe_class(ranges: &[(char, char)]) -> String {
+    let ranges = ranges.iter()
+        .map(|(from, to)| if from == to { from.to_string() } else { format!("{}-{}", from, to) })
+        .collect::<String>();
+    format!("[{}]", ranges)
+}
 
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+enum Symbol {
+    Terminal(char),
+    Class(Vec<(char, char)>),
+    Rule(usize)
+}
+
+// The rules flattened into alternatives of symbol sequences, parsed with a CYK style chart:
+// sequences are split in two at every position, so every sub-span is shorter than its parent
+// and only chains of single symbol alternatives stay on the same span.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Productions {
+    alternatives: HashMap<usize, Vec<Vec<Symbol>>>
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct ParseTree {
+    rule: usize,
+    start: usize,
+    end: usize,
+    children: Vec<ParseTree>
+}
+
+struct Chart<'a> {
+    productions: &'a Productions,
+    message: Vec<char>,
+    rules: HashMap<(usize, usize, usize), u128>,
+    sequences: HashMap<(usize, usize, usize, usize, usize), u128>
+}
+
+impl Productions {
+    fn count(&self, rule: usize, message: &str) -> u128 {
+        let mut chart = Chart::new(self, message);
+        let end = chart.message.len();
+        chart.rule(rule, 0, end)
+    }
+
+    // Every message of at most max_length characters derived from rule, in sorted order.
+    fn enumerate(&self, rule: usize, max_length: usize) -> Vec<String> {
+        let mut strings = HashMap::new();
+        (1..=max_length)
+            .flat_map(|length| self.strings(rule, length, &mut strings))
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect()
+    }
+
+    fn strings(&self, rule: usize, length: usize, strings: &mut HashMap<(usize, usize), BTreeSet<String>>) -> BTreeSet<String> {
+        if let Some(found) = strings.get(&(rule, length)) {
+            return found.clone();
+        }
+        let found = self.alternatives.get(&rule).cloned().unwrap_or_default().into_iter()
+            .flat_map(|symbols| self.sequence_strings(&symbols, length, strings))
+            .collect::<BTreeSet<String>>();
+        strings.insert((rule, length), found.clone());
+        found
+    }
+
+    fn sequence_strings(&self, symbols: &[Symbol], length: usize, strings: &mut HashMap<(usize, usize), BTreeSet<String>>) -> BTreeSet<String> {
+        let symbol_strings = |symbol: &Symbol, length: usize, strings: &mut HashMap<(usize, usize), BTreeSet<String>>| match symbol {
+            Symbol::Terminal(c) if length == 1 => vec!(c.to_string()).into_iter().collect(),
+            Symbol::Class(ranges) if length == 1 => ranges.iter()
+                .flat_map(|(from, to)| (*from..=*to).map(|c| c.to_string()))
+                .collect(),
+            Symbol::Rule(rule) => self.strings(*rule, length, strings),
+            _ => BTreeSet::new()
+        };
+        match symbols {
+            [] => BTreeSet::new(),
+            [last] => symbol_strings(last, length, strings),
+            [first, rest @ ..] => (1..length.saturating_sub(rest.len()) + 1)
+                .flat_map(|split| {
+                    let heads = symbol_strings(first, split, strings);
+                    let tails = if heads.is_empty() { BTreeSet::new() } else { self.sequence_strings(rest, length - split, strings) };
+                    heads.into_iter()
+                        .flat_map(|h| tails.iter().map(move |t| format!("{}{}", h, t)).collect::<Vec<String>>())
+                        .collect::<Vec<String>>()
+                })
+                .collect()
+        }
+    }
+
+    fn tree(&self, rule: usize, message: &str) -> Option<ParseTree> {
+        let mut chart = Chart::new(self, message);
+        let end = chart.message.len();
+        chart.tree(rule, 0, end)
+    }
+}
+
+impl<'a> Chart<'a> {
+    fn new(productions: &'a Productions, message: &str) -> Chart<'a> {
+        Chart { productions, message: message.chars().collect(), rules: HashMap::new(), sequences: HashMap::new() }
+    }
+
+    fn rule(&mut self, rule: usize, start: usize, end: usize) -> u128 {
+        if let Some(count) = self.rules.get(&(rule, start, end)) {
+            return *count;
+        }
+        let alternatives = self.productions.alternatives.get(&rule).map(|a| a.len()).unwrap_or(0);
+        let count = (0..alternatives)
+            .fold(0u128, |count, alternative| count.saturating_add(self.sequence(rule, alternative, 0, start, end)));
+        self.rules.insert((rule, start, end), count);
+        count
+    }
+
+    fn symbol(&mut self, symbol: &Symbol, start: usize, end: usize) -> u128 {
+        let single = end == start + 1;
+        match (symbol, self.message.get(start)) {
+            (Symbol::Terminal(c), Some(m)) => if single && c == m { 1 } else { 0 },
+            (Symbol::Class(ranges), Some(m)) => if single && in_class(ranges, *m) { 1 } else { 0 },
+            (Symbol::Rule(rule), _) => self.rule(*rule, start, end),
+            (_, None) => 0
+        }
+    }
+
+    // Parses of symbols[offset..] of an alternative over start..end, each symbol consuming at least one character.
+    fn sequence(&mut self, rule: usize, alternative: usize, offset: usize, start: usize, end: usize) -> u128 {
+        let key = (rule, alternative, offset, start, end);
+        if let Some(count) = self.sequences.get(&key) {
+            return *count;
+        }
+        let symbols = self.productions.alternatives[&rule][alternative].clone();
+        let remaining = symbols.len() - offset;
+        let count = if remaining == 1 {
+            self.symbol(&symbols[offset], start, end)
+        } else if end < start + remaining {
+            0
         } else {
-            let allowed = rule.split_whitespace().flat_map(|s| s.parse::<usize>().ok()).collect::<Vec<usize>>();
-            if(allowed.is_empty()) {
-                None
-            } else {
-                Some(Rule::Sequence(allowed))
-            }
+            (start + 1..=end + 1 - remaining).fold(0u128, |count, split| {
+                match self.symbol(&symbols[offset], start, split) {
+                    0 => count,
+                    head => count.saturating_add(head.saturating_mul(self.sequence(rule, alternative, offset + 1, split, end)))
+                }
+            })
+        };
+        self.sequences.insert(key, count);
+        count
+    }
+
+    fn tree(&mut self, rule: usize, start: usize, end: usize) -> Option<ParseTree> {
+        if self.rule(rule, start, end) == 0 {
+            return None;
         }
+        let alternatives = self.productions.alternatives[&rule].clone();
+        alternatives.iter().enumerate()
+            .find(|(alternative, _)| self.sequence(rule, *alternative, 0, start, end) > 0)
+            .and_then(|(alternative, symbols)| self.children(rule, alternative, symbols, 0, start, end))
+            .map(|children| ParseTree { rule, start, end, children })
     }
 
+    fn children(&mut self, rule: usize, alternative: usize, symbols: &[Symbol], offset: usize, start: usize, end: usize) -> Option<Vec<ParseTree>> {
+        let node = |chart: &mut Chart, symbol: &Symbol, start: usize, end: usize| match symbol {
+            Symbol::Terminal(_) | Symbol::Class(_) => Some(vec!()),
+            Symbol::Rule(r) => chart.tree(*r, start, end).map(|t| vec!(t))
+        };
+        if offset + 1 == symbols.len() {
+            return node(self, &symbols[offset], start, end);
+        }
+        let split = (start + 1..end).find(|split| self.symbol(&symbols[offset], start, *split) > 0
+            && self.sequence(rule, alternative, offset + 1, *split, end) > 0)?;
+        let mut children = node(self, &symbols[offset], start, split)?;
+        children.extend(self.children(rule, alternative, symbols, offset + 1, split, end)?);
+        Some(children)
+    }
+}
 
+impl ParseTree {
+    fn render(&self, message: &str) -> Vec<String> {
+        let text = message.chars().skip(self.start).take(self.end - self.start).collect::<String>();
+        let mut lines = vec!(format!("{} [{}..{}] {}", self.rule, self.start, self.end, text));
+        lines.extend(self.children.iter()
+            .flat_map(|c| c.render(message))
+            .map(|l| format!("  {}", l)));
+        lines
+    }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
+enum Expected {
+    Literal(char),
+    Class(Vec<(char, char)>),
+    End
+}
+
+// The furthest position any alternative reached before failing, what would have let it
+// continue there, and the rules all failures there have in common.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct Mismatch {
+    position: usize,
+    expected: BTreeSet<Expected>,
+    path: Vec<usize>
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let expected = self.expected.iter()
+            .map(|e| match e {
+                Expected::Literal(c) => format!("'{}'", c),
+                Expected::Class(ranges) => describe_class(ranges),
+                Expected::End => "end of message".to_string()
+            })
+            .collect::<Vec<String>>();
+        let path = self.path.iter().map(|r| r.to_string()).collect::<Vec<String>>();
+        write!(f, "expected {} at column {} via rule {}", expected.join(" or "), self.position + 1, path.join(" > "))
+    }
+}
+
+struct Explainer<'a> {
+    rules: &'a Rules,
+    message: Vec<char>,
+    positions: HashMap<(usize, usize), Vec<usize>>,
+    furthest: Option<Mismatch>
+}
+
+impl<'a> Explainer<'a> {
+    fn fail(&mut self, position: usize, expected: Expected, path: &[usize]) {
+        match &mut self.furthest {
+            Some(mismatch) if mismatch.position > position => {},
+            Some(mismatch) if mismatch.position == position => {
+                mismatch.expected.insert(expected);
+                let common = mismatch.path.iter().zip(path).take_while(|(a, b)| a == b).count();
+                mismatch.path.truncate(common);
+            },
+            _ => self.furthest = Some(Mismatch {
+                position,
+                expected: vec!(expected).into_iter().collect(),
+                path: path.to_vec()
+            })
+        }
+    }
+
+    // Every position the rule can end at when started at position.
+    fn rule(&mut self, idx: usize, position: usize, path: &mut Vec<usize>) -> Vec<usize> {
+        if let Some(ends) = self.positions.get(&(idx, position)) {
+            return ends.clone();
+        }
+        let rules = self.rules;
+        path.push(idx);
+        let ends = rules.rules.get(&idx)
+            .map(|rule| self.rule_body(rule, position, path))
+            .unwrap_or_default();
+        path.pop();
+        self.positions.insert((idx, position), ends.clone());
+        ends
+    }
+
+    fn rule_body(&mut self, rule: &Rule, position: usize, path: &mut Vec<usize>) -> Vec<usize> {
+        match rule {
+            Rule::Literal(literal) => match literal.chars().enumerate()
+                .find(|(i, c)| self.message.get(position + i) != Some(c)) {
+                Some((i, c)) => {
+                    self.fail(position + i, Expected::Literal(c), path);
+                    vec!()
+                },
+                None => vec!(position + literal.chars().count())
+            },
+            Rule::Class(ranges) => match self.message.get(position) {
+                Some(c) if in_class(ranges, *c) => vec!(position + 1),
+                _ => {
+                    self.fail(position, Expected::Class(ranges.clone()), path);
+                    vec!()
+                }
+            },
+            Rule::Or(rules) => {
+                let mut ends = rules.iter()
+                    .flat_map(|r| self.rule_body(r, position, path))
+                    .collect::<Vec<usize>>();
+                ends.sort_unstable();
+                ends.dedup();
+                ends
+            },
+            Rule::Sequence(rules) => rules.iter().fold(vec!(position), |positions, idx| {
+                let mut ends = positions.into_iter()
+                    .flat_map(|p| self.rule(*idx, p, path))
+                    .collect::<Vec<usize>>();
+                ends.sort_unstable();
+                ends.dedup();
+                ends
+            })
+        }
+    }
+}
+
+// xorshift64*, enough to make generated messages reproducible from a seed.
+struct Random {
+    state: u64
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random { state: (seed ^ 0x9e37_79b9_7f4a_7c15) | 1 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+// Random walks over the rules. Past max_depth only the alternatives closest to a literal are
+// taken, so recursive rules still terminate.
+struct Generator<'a> {
+    rules: &'a Rules,
+    random: Random,
+    max_depth: usize,
+    depths: HashMap<usize, usize>
+}
+
+impl<'a> Generator<'a> {
+    fn new(rules: &'a Rules, seed: u64, max_depth: usize) -> Generator<'a> {
+        Generator { rules, random: Random::new(seed), max_depth, depths: rules.depths() }
+    }
+
+    // None when rule 0 cannot derive any finite message.
+    fn generate(&mut self) -> Option<String> {
+        let mut message = String::new();
+        self.depths.get(&0)?;
+        self.generate_rule(0, 0, &mut message);
+        Some(message)
+    }
+
+    fn generate_rule(&mut self, idx: usize, depth: usize, message: &mut String) {
+        let rules = self.rules;
+        if let Some(rule) = rules.rules.get(&idx) {
+            self.generate_body(rule, depth, message)
+        }
+    }
+
+    fn generate_body(&mut self, rule: &Rule, depth: usize, message: &mut String) {
+        match rule {
+            Rule::Literal(literal) => message.push_str(literal),
+            Rule::Class(ranges) => message.extend(self.sample(ranges)),
+            Rule::Or(alternatives) => {
+                let candidates = alternatives.iter()
+                    .filter_map(|a| a.depth(&self.depths).map(|d| (a, d)))
+                    .collect::<Vec<(&Rule, usize)>>();
+                let shallowest = candidates.iter().map(|(_, d)| *d).min().unwrap_or(0);
+                let candidates = candidates.into_iter()
+                    .filter(|(_, d)| depth < self.max_depth || *d == shallowest)
+                    .map(|(a, _)| a)
+                    .collect::<Vec<&Rule>>();
+                let chosen = candidates[self.random.below(candidates.len())];
+                self.generate_body(chosen, depth, message)
+            },
+            Rule::Sequence(rules) => rules.iter().for_each(|r| self.generate_rule(*r, depth + 1, message))
+        }
+    }
+
+    // A random range first, then a character within it. None only for an empty list of ranges.
+    fn sample(&mut self, ranges: &[(char, char)]) -> Option<char> {
+        if ranges.is_empty() {
+            return None;
+        }
+        let (from, to) = ranges[self.random.below(ranges.len())];
+        let (from, to) = (from as u32, to as u32);
+        // a range can span the surrogates, which are not chars, so offsets skip over them
+        let gap = if from < 0xd800 && to > 0xdfff { 0x800 } else { 0 };
+        let code = from + self.random.below((to - from + 1 - gap) as usize) as u32;
+        std::char::from_u32(if code >= 0xd800 { code + gap } else { code })
+    }
+
+    // A single character substitution, insertion or deletion of message that the matcher rejects,
+    // using any character the rules' literals and classes can produce.
+    fn near_miss(&mut self, matcher: &Matcher, message: &str, attempts: usize) -> Option<String> {
+        let alphabet = self.rules.alphabet();
+        let chars = message.chars().collect::<Vec<char>>();
+        (0..attempts)
+            .map_while(|_| {
+                let mut mutated = chars.clone();
+                let position = self.random.below(chars.len() + 1);
+                let c = self.sample(&alphabet)?;
+                match self.random.below(3) {
+                    0 if position < chars.len() => mutated[position] = c,
+                    1 if position < chars.len() => { mutated.remove(position); },
+                    _ => mutated.insert(position, c)
+                }
+                Some(mutated.into_iter().collect::<String>())
+            })
+            .find(|mutated| matcher.matches(mutated.as_str()) == Ok(false))
+    }
+}
+
+const LOOPING_RULES: [&str; 2] = ["8: 42 | 42 8", "11: 42 31 | 42 11 31"];
+const SINGLE_RULES: [&str; 2] = ["8: 42", "11: 42 31"];
+
+// Non-recursive rules describe a regular language, so they can be matched by a single regex.
+enum Matcher<'a> {
+    Compiled(Regex),
+    Backtracking(&'a Rules)
+}
+
+impl<'a> Matcher<'a> {
+    fn matches(&self, value: &str) -> Result<bool, RuleError> {
+        match self {
+            Matcher::Compiled(regex) => Ok(regex.is_match(value)),
+            Matcher::Backtracking(rules) => match rules.valid(0, value) {
+                Ok(remaining) => Ok(remaining.into_iter().any(|r| r.is_empty())),
+                Err(RuleError::NoSuchRule(idx)) => Err(RuleError::NoSuchRule(idx)),
+                Err(_) => Ok(false)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 struct Rules {
     rules: HashMap<usize, Rule>
 }
 
 impl Rules {
-    fn from(file: &str) -> Rules {
-        Rules {
-            rules: super::lines(file).unwrap()
-                .into_iter()
-                .take_while(|l| !l.is_empty())
-                .flat_map(|l| {
-                    let mut parts = l.split(':');
-                    let idx = parts.next().and_then(|i| i.trim().parse::<usize>().ok());
-                    let rule = Rule::parse(parts.collect::<Vec<&str>>().join(":").as_str());
-                    match (idx, rule) {
-                        (Some(idx), Some(rule)) => Some((idx, rule)),
-                        _ => None
-                    }
-                })
-                .collect::<HashMap<usize, Rule>>()
+    fn from(file: &str) -> Result<Rules, Vec<RuleError>> {
+        super::lines(file)
+            .map_err(|e| vec!(RuleError::InvalidRule(format!("could not read {}: {}", file, e))))
+            .and_then(|lines| Rules::parse(&lines))
+    }
+
+    // Reads rule lines up to the first blank line, rejecting anything that does not parse
+    // and any reference to a rule that is never defined.
+    fn parse(lines: &[String]) -> Result<Rules, Vec<RuleError>> {
+        let mut rules = HashMap::new();
+        let mut errors = vec!();
+        lines.iter()
+            .take_while(|l| !l.is_empty())
+            .map(|l| Rules::parse_line(l.as_str()))
+            .for_each(|parsed| match parsed {
+                Ok((idx, _)) if rules.contains_key(&idx) => errors.push(RuleError::Duplicate(idx)),
+                Ok((idx, rule)) => { rules.insert(idx, rule); },
+                Err(e) => errors.push(e)
+            });
+        let rules = Rules { rules };
+        errors.extend(rules.validate());
+        if errors.is_empty() {
+            Ok(rules)
+        } else {
+            Err(errors)
         }
     }
 
-    fn evaluate(file: &str) -> Vec<Result<usize, RuleError>> {
-        let rules = Rules::from(file);
-        let data = super::lines(file)
+    fn parse_line(line: &str) -> Result<(usize, Rule), RuleError> {
+        let invalid = || RuleError::InvalidRule(line.to_string());
+        let (idx, rule) = line.split_once(':').ok_or_else(invalid)?;
+        let idx = idx.trim().parse::<usize>().map_err(|_| invalid())?;
+        Rule::parse(rule).map(|rule| (idx, rule))
+    }
+
+    fn validate(&self) -> Vec<RuleError> {
+        let mut ids = self.rules.keys().copied().collect::<Vec<usize>>();
+        ids.sort_unstable();
+        let undefined = ids.into_iter()
+            .flat_map(|rule| self.rules[&rule].references().into_iter()
+                .filter(|reference| !self.rules.contains_key(reference))
+                .map(move |reference| RuleError::Undefined { rule, reference }));
+        let missing_root = Some(RuleError::NoSuchRule(0)).filter(|_| !self.rules.contains_key(&0));
+        missing_root.into_iter().chain(undefined).collect()
+    }
+
+    // Shortest derivation depth of every rule that can derive a finite message.
+    fn depths(&self) -> HashMap<usize, usize> {
+        let mut depths: HashMap<usize, usize> = HashMap::new();
+        loop {
+            let next = self.rules.iter()
+                .flat_map(|(idx, rule)| rule.depth(&depths).map(|d| (*idx, d)))
+                .collect::<HashMap<usize, usize>>();
+            if next == depths {
+                return depths;
+            }
+            depths = next;
+        }
+    }
+
+    // Every character range a literal or class can produce, used to mutate messages.
+    fn alphabet(&self) -> Vec<(char, char)> {
+        self.rules.values().flat_map(Rule::ranges).collect::<BTreeSet<(char, char)>>().into_iter().collect()
+    }
+
+    // Rules that can never take part in matching rule 0.
+    fn unreachable(&self) -> Vec<usize> {
+        let mut reached = HashSet::new();
+        let mut pending = vec!(0);
+        while let Some(idx) = pending.pop() {
+            if reached.insert(idx) {
+                pending.extend(self.rules.get(&idx).map(|r| r.references()).unwrap_or_default());
+            }
+        }
+        let mut unreachable = self.rules.keys().filter(|idx| !reached.contains(idx)).copied().collect::<Vec<usize>>();
+        unreachable.sort_unstable();
+        unreachable
+    }
+
+    // Replaces (or adds) a single rule, e.g. "8: 42 | 42 8".
+    fn override_rule(&mut self, line: &str) -> Result<(), RuleError> {
+        let (idx, rule) = Rules::parse_line(line)?;
+        self.rules.insert(idx, rule);
+        Ok(())
+    }
+
+    fn with_overrides(mut self, lines: &[&str]) -> Result<Rules, Vec<RuleError>> {
+        let mut errors = lines.iter()
+            .flat_map(|line| self.override_rule(line).err())
+            .collect::<Vec<RuleError>>();
+        errors.extend(self.validate());
+        if errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn messages(file: &str) -> Vec<String> {
+        super::lines(file)
             .unwrap()
             .into_iter()
             .skip_while(|l| !l.is_empty())
             .skip_while(|l| l.is_empty())
-            .collect::<Vec<String>>();
-
+            .collect::<Vec<String>>()
+    }
 
-        (0..data.len()).into_iter()
-            .map(|idx| {
-                data.get(idx)
-                    .ok_or(RuleError::NoData(idx))
-                    .and_then(|l| rules.valid(0, l.as_str()))
-                    .and_then(|remaining|
-                        if(remaining.into_iter().any(|r| r.is_empty())) {
-                            Ok(idx)
-                        } else {
-                            Err(RuleError::HasMore(idx))
-                        }
-                    )
+    fn evaluate(file: &str) -> Result<Vec<Result<usize, RuleError>>, Vec<RuleError>> {
+        Rules::from(file).map(|rules| rules.check(&Rules::messages(file)))
+    }
 
+    // matcher() already rejects left recursion, so rejected messages can be explained directly.
+    fn check(&self, data: &[String]) -> Vec<Result<usize, RuleError>> {
+        let matcher = match self.matcher() {
+            Ok(matcher) => matcher,
+            Err(e) => return data.iter().map(|_| Err(e.clone())).collect()
+        };
+        data.iter().enumerate()
+            .map(|(idx, l)| match matcher.matches(l.as_str()) {
+                Ok(true) => Ok(idx),
+                Ok(false) => match self.furthest_mismatch(l.as_str()) {
+                    Some(mismatch) => Err(RuleError::Rejected(idx, mismatch)),
+                    None => Err(RuleError::Disagreement(idx))
+                },
+                Err(e) => Err(e)
             })
             .collect()
     }
 
+    // Every rule consumes at least one character, so as long as no rule can reach itself
+    // without consuming, each recursive step shrinks the input and backtracking terminates.
+    fn matcher(&self) -> Result<Matcher<'_>, RuleError> {
+        if let Some(idx) = self.left_recursive_rule() {
+            return Err(RuleError::LeftRecursive(idx));
+        }
+        if self.recursive_rule().is_some() {
+            return Ok(Matcher::Backtracking(self));
+        }
+        self.compile(0).map(Matcher::Compiled)
+    }
+
+    // Handles any rule set, including left recursion and ambiguity, unless rules can derive
+    // themselves on the same span which would allow infinitely many parses.
+    fn productions(&self) -> Result<Productions, RuleError> {
+        if let Some(idx) = self.find_cycles(Rule::units) {
+            return Err(RuleError::UnitCycle(idx));
+        }
+        if let Some(idx) = self.rules.values().flat_map(|r| r.references()).find(|r| !self.rules.contains_key(r)) {
+            return Err(RuleError::NoSuchRule(idx));
+        }
+        Ok(Productions {
+            alternatives: self.rules.iter().map(|(idx, rule)| (*idx, rule.alternatives())).collect()
+        })
+    }
+
+    // None when the message matches rule 0.
+    fn explain(&self, message: &str) -> Result<Option<Mismatch>, RuleError> {
+        if let Some(idx) = self.left_recursive_rule() {
+            return Err(RuleError::LeftRecursive(idx));
+        }
+        Ok(self.furthest_mismatch(message))
+    }
+
+    // Only terminates for rules without left recursion, callers have to check first.
+    fn furthest_mismatch(&self, message: &str) -> Option<Mismatch> {
+        let mut explainer = Explainer { rules: self, message: message.chars().collect(), positions: HashMap::new(), furthest: None };
+        let ends = explainer.rule(0, 0, &mut vec!());
+        let length = explainer.message.len();
+        if ends.contains(&length) {
+            return None;
+        }
+        ends.into_iter().for_each(|end| explainer.fail(end, Expected::End, &[0]));
+        explainer.furthest
+    }
+
+    // Only rules reachable from rule 0 take part in matching, cycles elsewhere don't matter.
+    fn recursive_rule(&self) -> Option<usize> {
+        self.recursive_rule_from(0)
+    }
+
+    fn recursive_rule_from(&self, idx: usize) -> Option<usize> {
+        self.find_cycle(idx, Rule::references, &mut vec!(), &mut HashSet::new())
+    }
+
+    fn left_recursive_rule(&self) -> Option<usize> {
+        self.find_cycles(Rule::leading)
+    }
+
+    fn find_cycles(&self, edges: fn(&Rule) -> Vec<usize>) -> Option<usize> {
+        let mut done = HashSet::new();
+        let mut ids = self.rules.keys().copied().collect::<Vec<usize>>();
+        ids.sort_unstable();
+        ids.into_iter().find_map(|idx| self.find_cycle(idx, edges, &mut vec!(), &mut done))
+    }
+
+    fn find_cycle(&self, idx: usize, edges: fn(&Rule) -> Vec<usize>, path: &mut Vec<usize>, done: &mut HashSet<usize>) -> Option<usize> {
+        if path.contains(&idx) {
+            return Some(idx);
+        }
+        if done.contains(&idx) {
+            return None;
+        }
+        path.push(idx);
+        let cycle = self.rules.get(&idx)
+            .map(edges)
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|next| self.find_cycle(next, edges, path, done));
+        path.pop();
+        done.insert(idx);
+        cycle
+    }
+
+    fn compile(&self, idx: usize) -> Result<Regex, RuleError> {
+        if let Some(rule) = self.recursive_rule_from(idx) {
+            return Err(RuleError::Recursive(rule));
+        }
+        let pattern = self.pattern(idx, &mut HashMap::new())?;
+        Regex::new(format!("^{}$", pattern).as_str()).map_err(|e| RuleError::Compile(e.to_string()))
+    }
+
+    fn pattern(&self, idx: usize, patterns: &mut HashMap<usize, String>) -> Result<String, RuleError> {
+        if let Some(pattern) = patterns.get(&idx) {
+            return Ok(pattern.clone());
+        }
+        let rule = self.rules.get(&idx).ok_or(RuleError::NoSuchRule(idx))?;
+        let pattern = self.rule_pattern(rule, patterns)?;
+        patterns.insert(idx, pattern.clone());
+        Ok(pattern)
+    }
+
+    fn rule_pattern(&self, rule: &Rule, patterns: &mut HashMap<usize, String>) -> Result<String, RuleError> {
+        match rule {
+            Rule::Literal(literal) => Ok(regex::escape(literal.as_str())),
+            Rule::Class(ranges) => Ok(format!("[{}]", ranges.iter()
+                .map(|(from, to)| format!("\\x{{{:x}}}-\\x{{{:x}}}", *from as u32, *to as u32))
+                .collect::<String>())),
+            Rule::Or(rules) => rules.iter()
+                .map(|r| self.rule_pattern(r, patterns))
+                .collect::<Result<Vec<String>, RuleError>>()
+                .map(|alternatives| format!("(?:{})", alternatives.join("|"))),
+            Rule::Sequence(rules) => rules.iter()
+                .map(|idx| self.pattern(*idx, patterns))
+                .collect::<Result<Vec<String>, RuleError>>()
+                .map(|parts| parts.concat())
+        }
+    }
+
     fn valid(&self, idx: usize, value: &str) -> Result<Vec<String>, RuleError> {
         match self.rules.get(&idx) {
             Some(rule) => self.valid_rule(rule.clone(), value),
@@ -106,22 +859,26 @@ impl Rules {
 
     fn valid_rule(&self, rule: Rule, value: &str) -> Result<Vec<String>, RuleError> {
         match rule {
-            Rule::Literal(c) =>
-                value.strip_prefix(c.to_string().as_str()).map(|s| Ok(vec!(s.to_string())))
-                    .unwrap_or(Err(RuleError::DoesNotMatch(c.clone()))),
-            Rule::Or(r1, r2) =>
-                match (
-                    self.valid_rule(r1.deref().clone(), value),
-                    self.valid_rule(r2.deref().borrow().clone(), value)) {
-                    (Ok(r1), Ok(r2)) => {
-                        let mut result = vec!();
-                        result.extend(r1); result.extend(r2);
-                        Ok(result)
-                    },
-                    (Ok(r1), Err(_)) => Ok(r1),
-                    (Err(_), Ok(r2)) => Ok(r2),
-                    (Err(e1), Err(e2)) => Err(RuleError::Aggregate(vec!(e1, e2)))
-                },
+            Rule::Literal(literal) =>
+                value.strip_prefix(literal.as_str()).map(|s| Ok(vec!(s.to_string())))
+                    .unwrap_or(Err(RuleError::DoesNotMatch(literal))),
+            Rule::Class(ranges) => {
+                let mut chars = value.chars();
+                match chars.next() {
+                    Some(c) if in_class(&ranges, c) => Ok(vec!(chars.collect::<String>())),
+                    _ => Err(RuleError::DoesNotMatch(describe_class(&ranges)))
+                }
+            },
+            Rule::Or(rules) => {
+                let (matched, failed): (Vec<_>, Vec<_>) = rules.into_iter()
+                    .map(|r| self.valid_rule(r, value))
+                    .partition(|r| r.is_ok());
+                if matched.is_empty() {
+                    Err(RuleError::Aggregate(failed.into_iter().flat_map(|r| r.err()).collect()))
+                } else {
+                    Ok(matched.into_iter().flat_map(|r| r.ok()).flatten().collect())
+                }
+            },
             Rule::Sequence(rules) => rules.into_iter()
                 .fold(Ok(vec!(value.to_string())), |res, rule_idx|
                     res.and_then(|strs| {
@@ -146,24 +903,373 @@ impl Rules {
     }
 }
 
+const INPUT: &str = "day-19-input.txt";
+
+fn load_rules(file: &str) -> Option<Rules> {
+    match Rules::from(file) {
+        Ok(rules) => Some(rules),
+        Err(errors) => {
+            errors.into_iter().for_each(|e| println!("invalid rules: {:?}", e));
+            None
+        }
+    }
+}
+
 pub fn run() {
-    let count = Rules::evaluate("day-19-input.txt").into_iter().filter(|r| r.is_ok()).count();
-    println!("There are {} valid rules", count)
+    let file = INPUT;
+    let rules = match load_rules(file) {
+        Some(rules) => rules,
+        None => return
+    };
+    let messages = Rules::messages(file);
+    let unreachable = rules.unreachable();
+    if !unreachable.is_empty() {
+        println!("rules {:?} are unreachable from rule 0", unreachable)
+    }
+    vec!(("without loops", SINGLE_RULES), ("with loops", LOOPING_RULES)).into_iter()
+        .for_each(|(name, overrides)| match rules.clone().with_overrides(&overrides) {
+            Ok(rules) => {
+                let count = rules.check(&messages).into_iter().filter(|r| r.is_ok()).count();
+                println!("There are {} valid messages {}", count, name)
+            },
+            Err(e) => println!("could not apply overrides {:?}", e)
+        });
+}
+
+// Every message of the file with the reason it is rejected, rules are used as written.
+fn check(file: &str) {
+    match Rules::evaluate(file) {
+        Ok(results) => {
+            results.iter().for_each(|r| match r {
+                Ok(_) => {},
+                Err(RuleError::Rejected(idx, mismatch)) => println!("message {}: {}", idx + 1, mismatch),
+                Err(e) => println!("{:?}", e)
+            });
+            println!("There are {} valid messages", results.iter().filter(|r| r.is_ok()).count())
+        },
+        Err(errors) => errors.into_iter().for_each(|e| println!("invalid rules: {:?}", e))
+    }
+}
+
+fn explain(rules: &Rules, message: &str) {
+    match rules.explain(message) {
+        Ok(Some(mismatch)) => println!("{} is rejected: {}", message, mismatch),
+        Ok(None) => match rules.productions() {
+            Ok(productions) => {
+                println!("{} matches with {} parses", message, productions.count(0, message));
+                productions.tree(0, message).into_iter()
+                    .flat_map(|tree| tree.render(message))
+                    .for_each(|l| println!("{}", l))
+            },
+            Err(e) => println!("{} matches, but has no finite parses: {:?}", message, e)
+        },
+        Err(e) => println!("could not explain {}: {:?}", message, e)
+    }
+}
+
+fn enumerate(rules: &Rules, max_length: usize) {
+    match rules.productions() {
+        Ok(productions) => productions.enumerate(0, max_length).into_iter().for_each(|m| println!("{}", m)),
+        Err(e) => println!("could not enumerate messages: {:?}", e)
+    }
+}
+
+// Messages of rule 0, each followed by a near miss the rules reject.
+fn generate(rules: &Rules, seed: u64, max_depth: usize, count: usize) {
+    let matcher = match rules.matcher() {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            println!("could not match generated messages: {:?}", e);
+            return;
+        }
+    };
+    let mut generator = Generator::new(rules, seed, max_depth);
+    for _ in 0..count {
+        let message = match generator.generate() {
+            Some(message) => message,
+            None => {
+                println!("rule 0 cannot derive a finite message");
+                return;
+            }
+        };
+        match generator.near_miss(&matcher, message.as_str(), 100) {
+            Some(miss) => println!("{} (near miss {})", message, miss),
+            None => println!("{} (no near miss found)", message)
+        }
+    }
+}
+
+// day19 check [file]
+// day19 explain <message> [file]
+// day19 enumerate [max length] [file]
+// day19 generate [seed] [max depth] [count] [file]
+pub fn run_args(args: &[String]) {
+    let file = |idx: usize| args.get(idx).map(|f| f.as_str()).unwrap_or(INPUT);
+    let number = |idx: usize, default: usize| args.get(idx)
+        .map(|n| n.parse::<usize>().map_err(|_| format!("not a number: {}", n)))
+        .unwrap_or(Ok(default));
+    match args.first().map(|a| a.as_str()) {
+        Some("check") => check(file(1)),
+        Some("explain") => match (args.get(1), load_rules(file(2))) {
+            (Some(message), Some(rules)) => explain(&rules, message),
+            (None, _) => println!("usage: day19 explain <message> [file]"),
+            (_, None) => {}
+        },
+        Some("enumerate") => match (number(1, 8), load_rules(file(2))) {
+            (Ok(max_length), Some(rules)) => enumerate(&rules, max_length),
+            (Err(e), _) => println!("{}", e),
+            (_, None) => {}
+        },
+        Some("generate") => match (number(1, 2020), number(2, 12), number(3, 10), load_rules(file(4))) {
+            (Ok(seed), Ok(max_depth), Ok(count), Some(rules)) => generate(&rules, seed as u64, max_depth, count),
+            (Err(e), _, _, _) | (_, Err(e), _, _) | (_, _, Err(e), _) => println!("{}", e),
+            (_, _, _, None) => {}
+        },
+        _ => run()
+    }
 }
 #[cfg(test)]
 mod test {
+    use crate::day19::{Generator, Matcher, Random, Rule, RuleError, Rules, LOOPING_RULES, SINGLE_RULES};
+    use std::collections::{BTreeSet, HashMap};
+
+    fn rules(lines: &[&str]) -> Rules {
+        let mut rules = Rules { rules: HashMap::new() };
+        lines.iter().for_each(|l| rules.override_rule(l).unwrap());
+        rules
+    }
+
+    fn load(file: &str) -> Rules {
+        Rules::from(file).unwrap()
+    }
+
     #[test]
     fn test_file() {
         assert_eq!(
-            super::Rules::evaluate("day-19-test.txt").into_iter().filter(|r| r.is_ok()).count(),
+            super::Rules::evaluate("day-19-test.txt").unwrap().into_iter().filter(|r| r.is_ok()).count(),
             2)
     }
 
     #[test]
     fn test_file_2() {
         assert_eq!(
-            super::Rules::evaluate("day-19-test-2.txt").into_iter().filter(|r| r.is_ok()).count(),
+            super::Rules::evaluate("day-19-test-2.txt").unwrap().into_iter().filter(|r| r.is_ok()).count(),
             12)
     }
 
+    #[test]
+    fn test_compile() {
+        let rules = load("day-19-test.txt");
+        assert_eq!(rules.recursive_rule(), None);
+        assert_eq!(rules.compile(2).map(|r| r.as_str().to_string()), Ok("^(?:aa|bb)$".to_string()));
+        let matcher = rules.matcher().unwrap();
+        assert!(matches!(matcher, Matcher::Compiled(_)));
+        vec!("ababbb", "bababa", "abbbab", "aaabbb", "aaaabbb").into_iter().for_each(|message|
+            assert_eq!(
+                matcher.matches(message),
+                Matcher::Backtracking(&rules).matches(message)));
+        assert_eq!(matcher.matches("abbbab"), Ok(true));
+        assert_eq!(matcher.matches("aaabbb"), Ok(false));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        // every rule doubles the one below it, 2^19 characters is more than the regex size limit
+        let mut lines = (0..19).map(|i| format!("{}: {} {}", i, i + 1, i + 1)).collect::<Vec<String>>();
+        lines.push("19: \"a\"".to_string());
+        let rules = Rules::parse(&lines).unwrap();
+        assert!(matches!(rules.matcher(), Err(RuleError::Compile(_))));
+        assert!(matches!(rules.check(&["a".to_string()])[0], Err(RuleError::Compile(_))));
+    }
+
+    #[test]
+    fn test_recursive_fallback() {
+        let rules = load("day-19-test-2.txt");
+        assert!(rules.recursive_rule().is_some());
+        assert!(matches!(rules.compile(0), Err(RuleError::Recursive(_))));
+        assert!(matches!(rules.matcher(), Ok(Matcher::Backtracking(_))));
+        assert_eq!(rules.matcher().unwrap().matches("babbbbaabbbbbabbbbbbaabaaabaaa"), Ok(true));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let file = "day-19-test-2.txt";
+        let count = |rules: Rules| rules.check(&Rules::messages(file)).into_iter().filter(|r| r.is_ok()).count();
+        let single = load(file).with_overrides(&SINGLE_RULES).unwrap();
+        assert_eq!(single.recursive_rule(), None);
+        assert_eq!(count(single), 3);
+        let looping = load(file).with_overrides(&SINGLE_RULES).and_then(|r| r.with_overrides(&LOOPING_RULES)).unwrap();
+        assert_eq!(looping.left_recursive_rule(), None);
+        assert_eq!(count(looping), 12);
+        assert_eq!(load(file).with_overrides(&["8 42"]).err(), Some(vec!(RuleError::InvalidRule("8 42".to_string()))));
+    }
+
+    #[test]
+    fn test_left_recursion() {
+        let file = "day-19-test-2.txt";
+        let direct = load(file).with_overrides(&["8: 8 42 | 42"]).unwrap();
+        assert_eq!(direct.matcher().err(), Some(RuleError::LeftRecursive(8)));
+        assert_eq!(direct.check(&Rules::messages(file))[0], Err(RuleError::LeftRecursive(8)));
+        let indirect = load(file).with_overrides(&["8: 11 | 42", "11: 8 31 | 42 31"]).unwrap();
+        assert!(matches!(indirect.matcher(), Err(RuleError::LeftRecursive(_))));
+    }
+
+    #[test]
+    fn test_chart_counts() {
+        let file = "day-19-test-2.txt";
+        let looping = load(file).with_overrides(&LOOPING_RULES).unwrap();
+        let productions = looping.productions().unwrap();
+        let messages = Rules::messages(file);
+        let accepted = messages.iter()
+            .map(|m| looping.matcher().unwrap().matches(m.as_str()) == Ok(true))
+            .collect::<Vec<bool>>();
+        assert_eq!(messages.iter().map(|m| productions.count(0, m.as_str()) > 0).collect::<Vec<bool>>(), accepted);
+
+        let left_recursive = rules(&["0: 0 1 | 1", "1: \"a\""]).productions().unwrap();
+        assert_eq!(left_recursive.count(0, "aaaa"), 1);
+        assert_eq!(left_recursive.count(0, "aaba"), 0);
+        let ambiguous = rules(&["0: 0 0 | 1", "1: \"a\""]).productions().unwrap();
+        assert_eq!((1..=5).map(|n| ambiguous.count(0, "a".repeat(n).as_str())).collect::<Vec<u128>>(), vec!(1, 1, 2, 5, 14));
+        assert_eq!(rules(&["0: 1 | 2", "1: 0 | 3", "2: \"a\"", "3: \"b\""]).productions().err(), Some(RuleError::UnitCycle(0)));
+        assert_eq!(rules(&["0: 1 2", "1: \"a\""]).productions().err(), Some(RuleError::NoSuchRule(2)));
+        let words = rules(&["0: 1 | 1 2 0", "1: \"ab\" | [x-z]", "2: \", \" | \"-\""]).productions().unwrap();
+        assert_eq!(words.count(0, "ab, z-ab"), 1);
+        assert_eq!(words.count(0, "ab, a"), 0);
+    }
+
+    #[test]
+    fn test_parse_tree() {
+        let productions = load("day-19-test.txt").productions().unwrap();
+        assert_eq!(productions.tree(0, "aaabbb"), None);
+        assert_eq!(productions.tree(0, "ababbb").unwrap().render("ababbb"), vec!(
+            "0 [0..6] ababbb",
+            "  4 [0..1] a",
+            "  1 [1..5] babb",
+            "    3 [1..3] ba",
+            "      5 [1..2] b",
+            "      4 [2..3] a",
+            "    2 [3..5] bb",
+            "      5 [3..4] b",
+            "      5 [4..5] b",
+            "  5 [5..6] b"));
+    }
+
+    #[test]
+    fn test_explain() {
+        let rules = load("day-19-test.txt");
+        let explain = |message: &str| rules.explain(message).unwrap().map(|m| m.to_string());
+        assert_eq!(explain("ababbb"), None);
+        assert_eq!(explain("aaabbb"), Some("expected 'a' at column 5 via rule 0 > 1 > 3 > 4".to_string()));
+        assert_eq!(explain("aaaabbb"), Some("expected end of message at column 7 via rule 0".to_string()));
+        assert_eq!(explain("b"), Some("expected 'a' at column 1 via rule 0 > 4".to_string()));
+        assert_eq!(explain("a"), Some("expected 'a' or 'b' at column 2 via rule 0 > 1 > 2".to_string()));
+        assert!(matches!(&rules.check(&["aaabbb".to_string()])[0], Err(RuleError::Rejected(0, m)) if m.position == 4));
+
+        let looping = load("day-19-test-2.txt").with_overrides(&LOOPING_RULES).unwrap();
+        assert_eq!(looping.explain("babbbbaabbbbbabbbbbbaabaaabaaa"), Ok(None));
+        assert!(looping.explain("aaaabbaaaabbaaa").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_rule_parsing() {
+        assert_eq!(Rule::parse(" \"a\" "), Ok(Rule::Literal("a".to_string())));
+        assert_eq!(Rule::parse("\"a\\\"b\""), Ok(Rule::Literal("a\"b".to_string())));
+        assert_eq!(Rule::parse("[a-cx_]"), Ok(Rule::Class(vec!(('a', 'c'), ('x', 'x'), ('_', '_')))));
+        assert_eq!(Rule::parse("1 2 | 3 | \"|\""), Ok(Rule::Or(vec!(
+            Rule::Sequence(vec!(1, 2)), Rule::Sequence(vec!(3)), Rule::Literal("|".to_string())))));
+        vec!("", "1 |", "| 1", "\"\"", "\"ab", "[]", "[c-a]", "1 \"a\"", "\"a\" \"b\"", "x").into_iter()
+            .for_each(|rule| assert_eq!(Rule::parse(rule), Err(RuleError::InvalidRule(rule.to_string())), "{}", rule));
+    }
+
+    #[test]
+    fn test_rule_validation() {
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect::<Vec<String>>();
+        assert_eq!(Rules::parse(&lines(&["0: 1 2", "1: \"a\"", "1: \"b\"", "3 4", "", "5: 6"])).err(), Some(vec!(
+            RuleError::Duplicate(1),
+            RuleError::InvalidRule("3 4".to_string()),
+            RuleError::Undefined { rule: 0, reference: 2 })));
+        assert_eq!(Rules::parse(&lines(&["1: \"a\""])).err(), Some(vec!(RuleError::NoSuchRule(0))));
+        let rules = Rules::parse(&lines(&["0: 1 | 2", "1: \"ab\"", "2: [0-9]", "3: 1 4", "4: 3"])).unwrap();
+        assert_eq!(rules.recursive_rule(), None);
+        assert_eq!(rules.unreachable(), vec!(3, 4));
+        assert_eq!(load("day-19-test.txt").unreachable(), Vec::<usize>::new());
+        let matcher = rules.matcher().unwrap();
+        assert!(matches!(matcher, Matcher::Compiled(_)));
+        assert_eq!(vec!("ab", "7", "a", "ab7").into_iter().map(|m| matcher.matches(m)).collect::<Vec<_>>(),
+                   vec!(Ok(true), Ok(true), Ok(false), Ok(false)));
+        assert_eq!(Matcher::Backtracking(&rules).matches("7"), Ok(true));
+        assert_eq!(rules.explain("a").unwrap().map(|m| m.to_string()),
+                   Some("expected 'b' at column 2 via rule 0 > 1".to_string()));
+        assert_eq!(rules.explain("x").unwrap().map(|m| m.to_string()),
+                   Some("expected 'a' or [0-9] at column 1 via rule 0".to_string()));
+        assert_eq!(load("day-19-test.txt").with_overrides(&["4: 9"]).err(), Some(vec!(RuleError::Undefined { rule: 4, reference: 9 })));
+    }
+
+    #[test]
+    fn test_random() {
+        let sample = |seed: u64| {
+            let mut random = Random::new(seed);
+            (0..5).map(|_| random.below(10)).collect::<Vec<usize>>()
+        };
+        assert_eq!(sample(7), sample(7));
+        assert_ne!(sample(7), sample(8));
+        let mut random = Random::new(0);
+        assert!((0..1000).all(|_| random.below(3) < 3));
+    }
+
+    #[test]
+    fn test_generate() {
+        let example = load("day-19-test.txt");
+        let matcher = example.matcher().unwrap();
+        let mut generator = Generator::new(&example, 19, 10);
+        let messages = (0..20).flat_map(|_| generator.generate()).collect::<Vec<String>>();
+        assert_eq!(messages.len(), 20);
+        assert!(messages.iter().all(|m| matcher.matches(m.as_str()) == Ok(true)));
+        let mut again = Generator::new(&example, 19, 10);
+        assert_eq!((0..20).flat_map(|_| again.generate()).collect::<Vec<String>>(), messages);
+
+        let looping = load("day-19-test-2.txt").with_overrides(&LOOPING_RULES).unwrap();
+        let matcher = looping.matcher().unwrap();
+        let mut generator = Generator::new(&looping, 2020, 12);
+        let messages = (0..10).flat_map(|_| generator.generate()).collect::<Vec<String>>();
+        assert_eq!(messages.len(), 10);
+        messages.into_iter().for_each(|m| {
+            assert_eq!(matcher.matches(m.as_str()), Ok(true), "{}", m);
+            let miss = generator.near_miss(&matcher, m.as_str(), 100).unwrap();
+            assert_eq!(matcher.matches(miss.as_str()), Ok(false));
+            assert!((miss.len() as isize - m.len() as isize).abs() <= 1);
+        });
+        assert_eq!(Generator::new(&rules(&["0: 0 1", "1: \"a\""]), 1, 10).generate(), None);
+        let classes = rules(&["0: 1 | [b-d]", "1: \"ab\""]);
+        assert_eq!(classes.alphabet(), vec!(('a', 'a'), ('b', 'b'), ('b', 'd')));
+        let letters = rules(&["0: [a-z]"]);
+        let matcher = letters.matcher().unwrap();
+        let mut generator = Generator::new(&letters, 3, 10);
+        let misses = (0..100).flat_map(|_| generator.near_miss(&matcher, "m", 10)).collect::<String>();
+        assert!(misses.chars().any(|c| c != 'a' && c != 'm' && c != 'z'), "{}", misses);
+        let empty = rules(&["0: 0"]);
+        assert_eq!(Generator::new(&empty, 1, 10).near_miss(&Matcher::Backtracking(&empty), "", 10), None);
+    }
+
+    #[test]
+    fn test_generate_class() {
+        let surrogates = rules(&["0: [\u{d7ff}-\u{e000}]"]);
+        let mut generator = Generator::new(&surrogates, 5, 10);
+        let messages = (0..200).flat_map(|_| generator.generate()).collect::<Vec<String>>();
+        let high = messages.iter().filter(|m| m.as_str() == "\u{e000}").count();
+        assert!(messages.iter().all(|m| m.as_str() == "\u{d7ff}" || m.as_str() == "\u{e000}"));
+        assert!(high > 50 && high < 150, "{}", high);
+    }
+
+    #[test]
+    fn test_enumerate() {
+        let productions = load("day-19-test.txt").productions().unwrap();
+        assert_eq!(productions.enumerate(0, 6), vec!(
+            "aaaabb", "aaabab", "abbabb", "abbbab", "aabaab", "aabbbb", "abaaab", "ababbb")
+            .into_iter().collect::<BTreeSet<&str>>().into_iter().map(|m| m.to_string()).collect::<Vec<String>>());
+        assert!(productions.enumerate(0, 5).is_empty());
+        let left_recursive = rules(&["0: 0 1 | 1", "1: [a-b]"]).productions().unwrap();
+        assert_eq!(left_recursive.enumerate(0, 2), vec!("a", "aa", "ab", "b", "ba", "bb"));
+    }
+
 }
@@ -41,23 +41,33 @@ fn lines(file: &str) -> io::Result<Vec<String>>  {
 
 
 fn main() {
-    // day1::run();
-    // day2::run();
-    // day3::run();
-    // day4::run();
-    // day5::run();
-    // day6::run();
-    // day7::run();
-    // day8::run();
-    // day9::run();
-    // day10::run();
-    // day11::run();
-    // day12::run();
-    // day13::run();
-    // day14::run();
-    // day15::run();
-    // day16::run();
-    // day17::run();
-    // day18::run();
-    day19::run();
-}
\ No newline at end of file
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    match args.first().map(|a| a.as_str()) {
+        Some("day15") => day15::run_args(&args[1..]),
+        Some("day16") => day16::run_args(&args[1..]),
+        Some("day17") => day17::run_args(&args[1..]),
+        Some("day18") => day18::run_args(&args[1..]),
+        Some("day19") => day19::run_args(&args[1..]),
+        _ => {
+            // day1::run();
+            // day2::run();
+            // day3::run();
+            // day4::run();
+            // day5::run();
+            // day6::run();
+            // day7::run();
+            // day8::run();
+            // day9::run();
+            // day10::run();
+            // day11::run();
+            // day12::run();
+            // day13::run();
+            // day14::run();
+            // day15::run();
+            // day16::run();
+            // day17::run();
+            // day18::run();
+            day19::run();
+        }
+    }
+}